@@ -0,0 +1,11 @@
+use afl::fuzz;
+use vodozemac::base64_decode;
+
+fn main() {
+    fuzz!(|data: &[u8]| {
+        if let Ok(decoded) = base64_decode(data) {
+            // Every four symbols decode into at most three bytes.
+            assert!(decoded.len() <= data.len() * 3 / 4, "Decoding should never grow the input");
+        }
+    });
+}
@@ -0,0 +1,19 @@
+use afl::fuzz;
+use vodozemac::{base64_decode, base64_encode};
+
+fn main() {
+    fuzz!(|data: &[u8]| {
+        if let Ok(decoded) = base64_decode(data) {
+            let encoded = base64_encode(&decoded);
+            assert_eq!(
+                encoded.as_bytes(),
+                data,
+                "Every byte sequence should have one valid encoding"
+            );
+        }
+
+        let decoded =
+            base64_decode(base64_encode(data)).expect("Encoded data should always decode");
+        assert_eq!(decoded, data);
+    });
+}
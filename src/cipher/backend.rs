@@ -0,0 +1,124 @@
+// Copyright 2022 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+/// The kind of implementation that is used for a cryptographic primitive.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Implementation {
+    /// A portable, constant-time implementation written in Rust.
+    Software,
+    /// An implementation that uses dedicated CPU instructions, e.g. AES-NI or
+    /// the SHA extensions.
+    Hardware,
+}
+
+/// The implementations of the primitives used to encrypt and authenticate
+/// Olm and Megolm messages on the current machine.
+///
+/// Returned by [`detect_backend()`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Backend {
+    /// The implementation of AES-256, used in CBC mode to encrypt messages.
+    pub aes: Implementation,
+    /// The implementation of SHA-256, used by HMAC to authenticate messages
+    /// and by HKDF to derive the message keys.
+    pub sha256: Implementation,
+}
+
+/// Detect which implementations of AES and SHA-256 are used on this machine.
+///
+/// The `aes` and `sha2` crates pick an implementation at runtime, based on
+/// the features the CPU supports. This function repeats that detection, so it
+/// can be used to log or assert that acceleration is available.
+///
+/// On `x86` and `x86_64`, AES-NI and the SHA extensions are picked up
+/// automatically, unless the `aes_force_soft` cfg flag is set.
+///
+/// On `aarch64`, the `aes` crate only uses the ARMv8 cryptography extensions
+/// if it is compiled with `RUSTFLAGS="--cfg aes_armv8"`. SHA-256 is only
+/// accelerated if the `asm` feature of the `sha2` crate is enabled, which
+/// can't be detected from here, so SHA-256 is always reported as
+/// [`Implementation::Software`] on this architecture.
+///
+/// All other architectures use the software implementations.
+pub fn detect_backend() -> Backend {
+    Backend { aes: implementation(detect::aes()), sha256: implementation(detect::sha256()) }
+}
+
+fn implementation(accelerated: bool) -> Implementation {
+    if accelerated {
+        Implementation::Hardware
+    } else {
+        Implementation::Software
+    }
+}
+
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+mod detect {
+    pub(super) fn aes() -> bool {
+        !cfg!(aes_force_soft) && std::is_x86_feature_detected!("aes")
+    }
+
+    pub(super) fn sha256() -> bool {
+        std::is_x86_feature_detected!("sha")
+            && std::is_x86_feature_detected!("sse2")
+            && std::is_x86_feature_detected!("ssse3")
+            && std::is_x86_feature_detected!("sse4.1")
+    }
+}
+
+#[cfg(target_arch = "aarch64")]
+mod detect {
+    pub(super) fn aes() -> bool {
+        cfg!(all(aes_armv8, not(aes_force_soft))) && std::arch::is_aarch64_feature_detected!("aes")
+    }
+
+    pub(super) fn sha256() -> bool {
+        false
+    }
+}
+
+#[cfg(not(any(target_arch = "x86", target_arch = "x86_64", target_arch = "aarch64")))]
+mod detect {
+    pub(super) fn aes() -> bool {
+        false
+    }
+
+    pub(super) fn sha256() -> bool {
+        false
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::detect_backend;
+
+    #[test]
+    fn detection_is_stable() {
+        assert_eq!(detect_backend(), detect_backend());
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    #[test]
+    fn detection_matches_the_cpu() {
+        use super::Implementation;
+
+        let expected = if std::is_x86_feature_detected!("aes") {
+            Implementation::Hardware
+        } else {
+            Implementation::Software
+        };
+
+        assert_eq!(detect_backend().aes, expected);
+    }
+}
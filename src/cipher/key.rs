@@ -34,6 +34,8 @@ struct ExpandedKeys(Box<[u8; 80]>);
 impl ExpandedKeys {
     const OLM_HKDF_INFO: &'static [u8] = b"OLM_KEYS";
     const MEGOLM_HKDF_INFO: &'static [u8] = b"MEGOLM_KEYS";
+    #[cfg(feature = "gcm")]
+    const MEGOLM_GCM_HKDF_INFO: &'static [u8] = b"MEGOLM_GCM_KEYS";
 
     fn new(message_key: &[u8; 32]) -> Self {
         Self::new_helper(message_key, Self::OLM_HKDF_INFO)
@@ -43,7 +45,12 @@ impl ExpandedKeys {
         Self::new_helper(message_key, Self::MEGOLM_HKDF_INFO)
     }
 
-    #[cfg(feature = "libolm-compat")]
+    #[cfg(feature = "gcm")]
+    fn new_megolm_gcm(message_key: &[u8; 128]) -> Self {
+        Self::new_helper(message_key, Self::MEGOLM_GCM_HKDF_INFO)
+    }
+
+    #[cfg(any(feature = "serde", feature = "libolm-compat"))]
     fn new_pickle(pickle_key: &[u8]) -> Self {
         Self::new_helper(pickle_key, b"Pickle")
     }
@@ -80,7 +87,16 @@ impl CipherKeys {
         Self::from_expanded_keys(expanded_keys)
     }
 
-    #[cfg(feature = "libolm-compat")]
+    /// Derive the keys for the AES-256-GCM Megolm mode. Only the AES key is
+    /// used, the IV and the HMAC key are derived but ignored.
+    #[cfg(feature = "gcm")]
+    pub fn new_megolm_gcm(message_key: &[u8; 128]) -> Self {
+        let expanded_keys = ExpandedKeys::new_megolm_gcm(message_key);
+
+        Self::from_expanded_keys(expanded_keys)
+    }
+
+    #[cfg(any(feature = "serde", feature = "libolm-compat"))]
     pub fn new_pickle(pickle_key: &[u8]) -> Self {
         let expanded_keys = ExpandedKeys::new_pickle(pickle_key);
 
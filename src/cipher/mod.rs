@@ -13,6 +13,7 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+mod backend;
 mod key;
 
 use aes::{
@@ -22,10 +23,16 @@ use aes::{
     },
     Aes256,
 };
+#[cfg(feature = "gcm")]
+use aes_gcm::{aead::AeadInPlace, Aes256Gcm, Nonce, Tag};
+pub use backend::{detect_backend, Backend, Implementation};
 use hmac::{digest::MacError, Hmac, Mac as MacT};
 use key::CipherKeys;
 use sha2::Sha256;
+#[cfg(any(feature = "serde", feature = "libolm-compat"))]
 use thiserror::Error;
+#[cfg(feature = "dangerous-export")]
+use zeroize::Zeroize;
 
 type Aes256CbcEnc = cbc::Encryptor<Aes256>;
 type Aes256CbcDec = cbc::Decryptor<Aes256>;
@@ -35,9 +42,13 @@ type HmacSha256 = Hmac<Sha256>;
 pub struct Mac(pub(crate) [u8; Self::LENGTH]);
 
 impl Mac {
+    /// The length of a full, untruncated, HMAC-SHA-256 MAC.
     pub const LENGTH: usize = 32;
+    /// The length of a truncated MAC, Olm and Megolm version 1 as well as
+    /// libolm pickles only use the first 8 bytes of the MAC.
     pub const TRUNCATED_LEN: usize = 8;
 
+    /// Truncate the MAC to its first [`Mac::TRUNCATED_LEN`] bytes.
     pub fn truncate(&self) -> [u8; Self::TRUNCATED_LEN] {
         let mut truncated = [0u8; Self::TRUNCATED_LEN];
         truncated.copy_from_slice(&self.0[0..Self::TRUNCATED_LEN]);
@@ -50,6 +61,13 @@ impl Mac {
     }
 }
 
+/// The length of an AES-GCM authentication tag.
+#[cfg(feature = "gcm")]
+pub(crate) const GCM_TAG_LEN: usize = 16;
+/// The length of an AES-GCM nonce.
+#[cfg(feature = "gcm")]
+pub(crate) const GCM_NONCE_LEN: usize = 12;
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub(crate) enum MessageMac {
     Truncated([u8; Mac::TRUNCATED_LEN]),
@@ -77,7 +95,9 @@ impl From<[u8; Mac::TRUNCATED_LEN]> for MessageMac {
     }
 }
 
+#[cfg(any(feature = "serde", feature = "libolm-compat"))]
 #[derive(Debug, Error)]
+#[non_exhaustive]
 pub enum DecryptionError {
     #[error("Failed decrypting, invalid padding")]
     InvalidPadding(#[from] UnpadError),
@@ -92,6 +112,22 @@ pub struct Cipher {
     keys: CipherKeys,
 }
 
+/// The AES key, AES IV and HMAC key derived from a single message key.
+///
+/// **Warning**: These keys decrypt and authenticate the message they were
+/// derived for.
+#[cfg(feature = "dangerous-export")]
+#[derive(Zeroize)]
+#[zeroize(drop)]
+pub struct MessageKeys {
+    /// The AES-256 key used to encrypt the message.
+    pub aes_key: [u8; 32],
+    /// The initialization vector used for the AES-256 CBC encryption.
+    pub aes_iv: [u8; 16],
+    /// The HMAC-SHA-256 key used to authenticate the message.
+    pub mac_key: [u8; 32],
+}
+
 impl Cipher {
     pub fn new(key: &[u8; 32]) -> Self {
         let keys = CipherKeys::new(key);
@@ -105,13 +141,34 @@ impl Cipher {
         Self { keys }
     }
 
-    #[cfg(feature = "libolm-compat")]
+    /// Create a cipher for the AES-256-GCM Megolm mode.
+    ///
+    /// The key is derived from the ratchet using a different HKDF info than
+    /// [`Cipher::new_megolm()`], the two modes never share an AES key.
+    #[cfg(feature = "gcm")]
+    pub fn new_megolm_gcm(&key: &[u8; 128]) -> Self {
+        let keys = CipherKeys::new_megolm_gcm(&key);
+
+        Self { keys }
+    }
+
+    #[cfg(any(feature = "serde", feature = "libolm-compat"))]
     pub fn new_pickle(key: &[u8]) -> Self {
         let keys = CipherKeys::new_pickle(key);
 
         Self { keys }
     }
 
+    /// Copy out the keys this cipher uses.
+    #[cfg(feature = "dangerous-export")]
+    pub fn message_keys(&self) -> MessageKeys {
+        MessageKeys {
+            aes_key: (*self.keys.aes_key()).into(),
+            aes_iv: (*self.keys.iv()).into(),
+            mac_key: *self.keys.mac_key(),
+        }
+    }
+
     fn get_hmac(&self) -> HmacSha256 {
         // We don't use HmacSha256::new() here because it expects a 64-byte
         // large HMAC key while the Olm spec defines a 32-byte one instead.
@@ -137,27 +194,107 @@ impl Cipher {
         Mac(mac)
     }
 
+    /// Calculate a MAC for the given message and truncate it to
+    /// [`Mac::TRUNCATED_LEN`] bytes.
+    ///
+    /// This is the counterpart of [`Cipher::verify_truncated_mac()`].
+    #[cfg(any(feature = "serde", feature = "libolm-compat"))]
+    pub fn truncated_mac(&self, message: &[u8]) -> [u8; Mac::TRUNCATED_LEN] {
+        self.mac(message).truncate()
+    }
+
     pub fn decrypt(&self, ciphertext: &[u8]) -> Result<Vec<u8>, UnpadError> {
+        let mut plaintext = Vec::new();
+        self.decrypt_into(ciphertext, &mut plaintext)?;
+
+        Ok(plaintext)
+    }
+
+    /// Decrypt the ciphertext into the given buffer, reusing its capacity.
+    ///
+    /// The buffer is cleared first, on failure it's left empty.
+    pub fn decrypt_into(&self, ciphertext: &[u8], out: &mut Vec<u8>) -> Result<(), UnpadError> {
+        out.clear();
+        out.extend_from_slice(ciphertext);
+
         let cipher = Aes256CbcDec::new(self.keys.aes_key(), self.keys.iv());
-        cipher.decrypt_padded_vec_mut::<Pkcs7>(ciphertext)
+
+        match cipher.decrypt_padded_mut::<Pkcs7>(out) {
+            Ok(plaintext) => {
+                let length = plaintext.len();
+                out.truncate(length);
+
+                Ok(())
+            }
+            Err(e) => {
+                out.clear();
+                Err(e)
+            }
+        }
+    }
+
+    /// Encrypt the plaintext using AES-256-GCM, returning the ciphertext and
+    /// the detached authentication tag.
+    #[cfg(feature = "gcm")]
+    pub fn encrypt_gcm(
+        &self,
+        nonce: &[u8; GCM_NONCE_LEN],
+        plaintext: &[u8],
+    ) -> (Vec<u8>, [u8; GCM_TAG_LEN]) {
+        let cipher = <Aes256Gcm as aes_gcm::KeyInit>::new(self.keys.aes_key());
+        let mut ciphertext = plaintext.to_vec();
+
+        let tag = cipher
+            .encrypt_in_place_detached(Nonce::from_slice(nonce), &[], &mut ciphertext)
+            .expect("The plaintext should be shorter than the AES-GCM limit of 64 GiB");
+
+        (ciphertext, tag.into())
+    }
+
+    /// Check the authentication tag and decrypt the ciphertext using
+    /// AES-256-GCM into the given buffer, reusing its capacity.
+    ///
+    /// The buffer is cleared first, on failure it's left empty.
+    #[cfg(feature = "gcm")]
+    pub fn decrypt_gcm_into(
+        &self,
+        nonce: &[u8; GCM_NONCE_LEN],
+        ciphertext: &[u8],
+        tag: &[u8; GCM_TAG_LEN],
+        out: &mut Vec<u8>,
+    ) -> Result<(), MacError> {
+        let cipher = <Aes256Gcm as aes_gcm::KeyInit>::new(self.keys.aes_key());
+
+        out.clear();
+        out.extend_from_slice(ciphertext);
+
+        cipher
+            .decrypt_in_place_detached(Nonce::from_slice(nonce), &[], out, Tag::from_slice(tag))
+            .map_err(|_| {
+                out.clear();
+                MacError
+            })
     }
 
+    #[cfg(any(feature = "serde", feature = "libolm-compat"))]
     pub fn decrypt_pickle(&self, ciphertext: &[u8]) -> Result<Vec<u8>, DecryptionError> {
         if ciphertext.len() < Mac::TRUNCATED_LEN + 1 {
             Err(DecryptionError::MacMissing)
         } else {
             let (ciphertext, mac) = ciphertext.split_at(ciphertext.len() - Mac::TRUNCATED_LEN);
+            let mac = mac.try_into().expect("The MAC slice should have the truncated MAC length");
             self.verify_truncated_mac(ciphertext, mac)?;
 
             Ok(self.decrypt(ciphertext)?)
         }
     }
 
+    #[cfg(any(feature = "serde", feature = "libolm-compat"))]
     pub fn encrypt_pickle(&self, plaintext: &[u8]) -> Vec<u8> {
         let mut ciphertext = self.encrypt(plaintext);
-        let mac = self.mac(&ciphertext);
+        let mac = self.truncated_mac(&ciphertext);
 
-        ciphertext.extend(mac.truncate());
+        ciphertext.extend(mac);
 
         ciphertext
     }
@@ -170,8 +307,13 @@ impl Cipher {
         hmac.verify_slice(tag.as_bytes())
     }
 
+    /// Verify a MAC that was truncated to [`Mac::TRUNCATED_LEN`] bytes.
     #[cfg(not(fuzzing))]
-    pub fn verify_truncated_mac(&self, message: &[u8], tag: &[u8]) -> Result<(), MacError> {
+    pub fn verify_truncated_mac(
+        &self,
+        message: &[u8],
+        tag: &[u8; Mac::TRUNCATED_LEN],
+    ) -> Result<(), MacError> {
         let mut hmac = self.get_hmac();
 
         hmac.update(message);
@@ -188,7 +330,56 @@ impl Cipher {
     }
 
     #[cfg(fuzzing)]
-    pub fn verify_truncated_mac(&self, _: &[u8], _: &[u8]) -> Result<(), MacError> {
+    pub fn verify_truncated_mac(
+        &self,
+        _: &[u8],
+        _: &[u8; Mac::TRUNCATED_LEN],
+    ) -> Result<(), MacError> {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::{Cipher, Mac};
+
+    const MESSAGE: &[u8] = b"It's dangerous to go alone";
+
+    #[test]
+    fn mac_roundtrip() {
+        let cipher = Cipher::new(&[1u8; 32]);
+        let mac = cipher.mac(MESSAGE);
+
+        assert_eq!(mac.as_bytes().len(), Mac::LENGTH);
+        cipher.verify_mac(MESSAGE, &mac).expect("The MAC should be valid");
+        cipher.verify_mac(b"Take this", &mac).expect_err("The MAC should not be valid");
+    }
+
+    #[test]
+    #[cfg(any(feature = "serde", feature = "libolm-compat"))]
+    fn truncated_mac_roundtrip() {
+        let cipher = Cipher::new(&[1u8; 32]);
+        let mac = cipher.truncated_mac(MESSAGE);
+
+        assert_eq!(mac.len(), Mac::TRUNCATED_LEN);
+        assert_eq!(mac, cipher.mac(MESSAGE).as_bytes()[..Mac::TRUNCATED_LEN]);
+
+        cipher.verify_truncated_mac(MESSAGE, &mac).expect("The truncated MAC should be valid");
+        cipher
+            .verify_truncated_mac(b"Take this", &mac)
+            .expect_err("The truncated MAC should not be valid");
+    }
+
+    #[test]
+    #[cfg(any(feature = "serde", feature = "libolm-compat"))]
+    fn pickle_roundtrip() {
+        let cipher = Cipher::new(&[1u8; 32]);
+        let mut ciphertext = cipher.encrypt_pickle(MESSAGE);
+
+        assert_eq!(cipher.decrypt_pickle(&ciphertext).expect("Can't decrypt the pickle"), MESSAGE);
+
+        let last = ciphertext.len() - 1;
+        ciphertext[last] ^= 1;
+        cipher.decrypt_pickle(&ciphertext).expect_err("The tampered pickle should not decrypt");
+    }
+}
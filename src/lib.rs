@@ -91,6 +91,14 @@
 //!
 //! # Feature flags
 //!
+//! ## Serde
+//!
+//! Feature: `serde` (default: on)
+//!
+//! Implements `Serialize` and `Deserialize` for the public types of vodozemac
+//! and enables [modern pickles](#modern-pickles). Services that don't need to
+//! store any state, e.g. ones which only verify signatures, can disable it.
+//!
 //! ## Low-level API
 //!
 //! Feature: `low-level-api` (default: off)
@@ -102,6 +110,88 @@
 //! Extreme care must be taken when using such APIs, as incorrect usage can lead
 //! to broken sessions.
 //!
+//! ## Parallel Megolm decryption
+//!
+//! Feature: `rayon` (default: off)
+//!
+//! Adds `megolm::decrypt_batch()`, which decrypts the messages of many
+//! independent Megolm sessions in parallel using the [rayon] thread pool.
+//!
+//! [rayon]: https://docs.rs/rayon
+//!
+//! ## Skipping Megolm signature verification
+//!
+//! Feature: `no-verify` (default: off)
+//!
+//! Compiles out the verification of the Ed25519 signatures on Megolm session
+//! keys and Megolm messages. The signing key is still decoded and stored, so
+//! the signatures can be verified outside of vodozemac.
+//!
+//! **Warning**: This is only safe to use if the signatures are verified by
+//! other means, for example if the verification is delegated to a secure
+//! element. Without verification, anybody who knows the ratchet state of a
+//! session can forge messages for it.
+//!
+//! ## Exporting raw ratchet state
+//!
+//! Feature: `dangerous-export` (default: off)
+//!
+//! Adds `GroupSession::__dangerous_ratchet_bytes()` and
+//! `InboundGroupSession::__dangerous_ratchet_bytes()`, which return the raw
+//! ratchet state of a Megolm session in a buffer that is zeroized on drop, and
+//! `InboundGroupSession::message_keys()`, which derives the keys of individual
+//! messages, for forensic debugging.
+//!
+//! **Warning**: The ratchet state allows decrypting every future message of
+//! the session. This feature must never be enabled in production builds.
+//!
+//! ## ed25519-dalek internals
+//!
+//! Feature: `dalek-internals` (default: off)
+//!
+//! Adds `Ed25519PublicKey::as_verifying_key()`, which gives access to the
+//! underlying [ed25519-dalek] key for operations vodozemac doesn't wrap.
+//!
+//! **Warning**: The exposed types belong to ed25519-dalek, any upgrade of
+//! ed25519-dalek in vodozemac may break code that uses them.
+//!
+//! [ed25519-dalek]: https://docs.rs/ed25519-dalek
+//!
+//! ## AES-GCM Megolm sessions
+//!
+//! Feature: `gcm` (default: off)
+//!
+//! Adds `megolm::SessionConfig::gcm()`, a Megolm mode which encrypts messages
+//! using AES-256-GCM instead of AES-256-CBC and HMAC-SHA-256.
+//!
+//! **Warning**: This mode is specific to vodozemac. Messages encrypted with it
+//! can't be decrypted by libolm or any other Megolm implementation, it's only
+//! suitable for applications which don't need to interoperate with Matrix.
+//!
+//! ## Test helpers
+//!
+//! Feature: `testing` (default: off)
+//!
+//! Adds `Curve25519PublicKey::random()`, which creates a public key without
+//! handing out the matching secret key, e.g. to populate device lists in
+//! tests, `Account::verify_own_signature()`, which checks a signature against
+//! the account's own fingerprint key, and `MegolmMessage::encode()`, which
+//! builds deliberately malformed Megolm messages.
+//!
+//! # Hardware acceleration
+//!
+//! Olm and Megolm messages are encrypted with AES-256 and authenticated with
+//! HMAC-SHA-256. The implementations of both primitives are selected at
+//! runtime:
+//!
+//! - On `x86` and `x86_64`, AES-NI and the SHA extensions are used if the CPU
+//!   supports them. No configuration is needed.
+//! - On `aarch64`, the ARMv8 AES instructions are only used if the crate is
+//!   compiled with `RUSTFLAGS="--cfg aes_armv8"`. Accelerated SHA-256 requires
+//!   enabling the `asm` feature of the `sha2` crate in your own manifest.
+//!
+//! Use [`detect_backend()`] to find out which implementations are in use.
+//!
 //! # Pickling
 //!
 //! vodozemac supports serializing its entire internal state into a form
@@ -122,6 +212,10 @@
 //! nor specified by this crate, but you can serialize to and deserialize from
 //! any format supported by Serde.
 //!
+//! Modern pickles require the `serde` feature. The version of the format this
+//! build produces is available as [`PICKLE_VERSION`], migration tooling can
+//! compare it against the version it last pickled with.
+//!
 //! The following structs support pickling:
 //!
 //! - [`olm::Account`]
@@ -134,11 +228,15 @@
 //! in memory:
 //!
 //! ```rust
+//! # #[cfg(feature = "serde")]
 //! use anyhow::Result;
+//! # #[cfg(feature = "serde")]
 //! use vodozemac::olm::{Account, AccountPickle};
 //!
+//! # #[cfg(feature = "serde")]
 //! const PICKLE_KEY: [u8; 32] = [0u8; 32];
 //!
+//! # #[cfg(feature = "serde")]
 //! fn main() -> Result<()>{
 //!     let mut account = Account::new();
 //!
@@ -153,16 +251,18 @@
 //!
 //!     Ok(())
 //! }
+//! # #[cfg(not(feature = "serde"))]
+//! # fn main() {}
 //! ```
 //!
 //! You can unpickle a pickle-able struct directly from its serialized form:
 //!
 //! ```rust
-//! # use anyhow::Result;
-//! # use vodozemac::olm::{Account, AccountPickle};
-//! # use zeroize::Zeroize;
+//! # #[cfg(feature = "serde")]
+//! # fn main() -> anyhow::Result<()> {
+//! #   use vodozemac::olm::{Account, AccountPickle};
+//! #   use zeroize::Zeroize;
 //! #
-//! # fn main() -> Result<()> {
 //! #   let some_account = Account::new();
 //!     let mut json_str = serde_json::to_string(&some_account.pickle())?;
 //!     // This will produce an account which is identical to `some_account`.
@@ -172,6 +272,8 @@
 //! #
 //! #    Ok(())
 //! # }
+//! # #[cfg(not(feature = "serde"))]
+//! # fn main() {}
 //! ```
 //!
 //! However, the pickle-able structs do not implement `serde::Serialize`
@@ -182,15 +284,20 @@
 //! from such as serializeable struct, just call `.unpickle()`.
 //!
 //! ```rust
+//! # #[cfg(feature = "serde")]
 //! use anyhow::Result;
+//! # #[cfg(feature = "serde")]
 //! use vodozemac::olm::Account;
 //!
+//! # #[cfg(feature = "serde")]
 //! fn main() -> Result<()> {
 //!     let account = Account::new();
 //!     let account: Account = account.pickle().into();  // this is identity
 //!
 //!     Ok(())
 //! }
+//! # #[cfg(not(feature = "serde"))]
+//! # fn main() {}
 //! ```
 
 #![deny(
@@ -217,20 +324,36 @@ pub mod megolm;
 pub mod olm;
 pub mod sas;
 
-pub use base64::DecodeError as Base64DecodeError;
+pub use cipher::{detect_backend, Backend, Implementation};
 pub use prost::DecodeError as ProtoBufDecodeError;
 pub use types::{
-    Curve25519PublicKey, Ed25519Keypair, Ed25519PublicKey, Ed25519SecretKey, Ed25519Signature,
-    KeyError, KeyId, SignatureError,
+    Curve25519PublicKey, Curve25519SecretKey, DeviceCurve25519Key, Ed25519Keypair, Ed25519Prehash,
+    Ed25519PublicKey, Ed25519SecretKey, Ed25519Signature, KeyError, KeyId, SignatureError,
 };
+#[cfg(feature = "low-level-api")]
+pub use utilities::{base64_decode, base64_encode};
+pub use utilities::{ct_eq_base64, DecodeError as Base64DecodeError};
+
+/// The version of the modern pickle format this build of vodozemac produces.
+///
+/// The version is bumped whenever the pickle of any of the pickleable structs
+/// changes in a way older versions of vodozemac can't unpickle. Pickles don't
+/// contain the version themselves, it needs to be stored next to them.
+///
+/// Version `1` is the format vodozemac 0.3.0 produces, the pickle format
+/// hasn't changed since.
+#[cfg(feature = "serde")]
+pub const PICKLE_VERSION: u32 = 1;
 
 /// Error type describing the various ways Vodozemac pickles can fail to be
 /// decoded.
+#[cfg(feature = "serde")]
 #[derive(Debug, thiserror::Error)]
+#[non_exhaustive]
 pub enum PickleError {
     /// The pickle wasn't valid base64.
     #[error("The pickle wasn't valid base64: {0}")]
-    Base64(#[from] base64::DecodeError),
+    Base64(#[from] Base64DecodeError),
     /// The encrypted pickle could not have been decrypted.
     #[error("The pickle couldn't be decrypted: {0}")]
     Decryption(#[from] crate::cipher::DecryptionError),
@@ -240,9 +363,10 @@ pub enum PickleError {
 }
 
 /// Error type describing the various ways libolm pickles can fail to be
-/// decoded.
+/// decoded or created.
 #[cfg(feature = "libolm-compat")]
 #[derive(Debug, thiserror::Error)]
+#[non_exhaustive]
 pub enum LibolmPickleError {
     /// The pickle is missing a valid version.
     #[error("The pickle doesn't contain a version")]
@@ -264,12 +388,20 @@ pub enum LibolmPickleError {
     #[error("The pickle didn't contain a valid Olm session")]
     InvalidSession,
     /// The payload of the pickle could not be decoded.
-    #[error(transparent)]
+    #[error("The pickle couldn't be decoded")]
     Decode(#[from] matrix_pickle::DecodeError),
+    /// The session uses a Megolm [`SessionConfig`] libolm doesn't support,
+    /// it can't be pickled in the libolm format. Contains the version of the
+    /// config.
+    ///
+    /// [`SessionConfig`]: megolm::SessionConfig
+    #[error("libolm doesn't support Megolm sessions using the session config version {0}")]
+    UnsupportedSessionConfig(u8),
 }
 
 /// Error type describing the different ways message decoding can fail.
 #[derive(Debug, thiserror::Error)]
+#[non_exhaustive]
 pub enum DecodeError {
     /// The Olm message has an invalid type.
     #[error("The message has an invalid type, expected 0 or 1, got {0}")]
@@ -293,7 +425,7 @@ pub enum DecodeError {
     #[error("The message contained an invalid Signature: {0}")]
     Signature(#[from] SignatureError),
     /// The message couldn't be decoded as a valid protocol buffer message.
-    #[error(transparent)]
+    #[error("The message couldn't be decoded as a protocol buffer")]
     ProtoBufError(#[from] ProtoBufDecodeError),
     /// The message wasn't valid base64.
     #[error("The message wasn't valid base64: {0}")]
@@ -316,6 +448,19 @@ fn corpus_data_path(fuzz_target: &str) -> std::path::PathBuf {
     afl_dir
 }
 
+#[cfg(all(test, feature = "serde"))]
+fn fixture(name: &str) -> String {
+    let manifest_dir =
+        std::env::var("CARGO_MANIFEST_DIR").expect("Cargo always sets the manifest dir");
+
+    let mut path = std::path::PathBuf::from(manifest_dir);
+    path.push("tests");
+    path.push("fixtures");
+    path.push(name);
+
+    std::fs::read_to_string(path).expect("Couldn't read the fixture").trim().to_owned()
+}
+
 #[cfg(test)]
 fn run_corpus<F>(fuzz_target: &str, method: F)
 where
@@ -330,3 +475,68 @@ where
         method(&data)
     }
 }
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        megolm::{
+            ExportedSessionKey, GroupSession, InboundGroupSession, MegolmMessage, SessionKey,
+        },
+        olm::{Account, OlmMessage, Session},
+        sas::{EstablishedSas, Sas},
+        Curve25519PublicKey, DecodeError, Ed25519Keypair, Ed25519PublicKey, Ed25519SecretKey,
+        Ed25519Signature, KeyError, SignatureError,
+    };
+
+    fn assert_send_sync<T: Send + Sync>() {}
+
+    fn assert_error<T: std::error::Error + Send + Sync + 'static>() {}
+
+    /// Our types are commonly shared between threads, make sure that we don't
+    /// accidentally lose the `Send` and `Sync` bounds.
+    #[test]
+    fn public_types_are_send_and_sync() {
+        assert_send_sync::<GroupSession>();
+        assert_send_sync::<InboundGroupSession>();
+        assert_send_sync::<SessionKey>();
+        assert_send_sync::<ExportedSessionKey>();
+        assert_send_sync::<MegolmMessage>();
+
+        assert_send_sync::<Account>();
+        assert_send_sync::<Session>();
+        assert_send_sync::<OlmMessage>();
+
+        assert_send_sync::<Sas>();
+        assert_send_sync::<EstablishedSas>();
+
+        assert_send_sync::<Curve25519PublicKey>();
+        assert_send_sync::<Ed25519Keypair>();
+        assert_send_sync::<Ed25519PublicKey>();
+        assert_send_sync::<Ed25519SecretKey>();
+        assert_send_sync::<Ed25519Signature>();
+    }
+
+    /// All public error types need to implement `std::error::Error` and be
+    /// `Send + Sync`. The trait is only available as `core::error::Error`
+    /// from Rust 1.81 on, which is above our MSRV, so the `no_std` switch is
+    /// left for when the MSRV is raised.
+    #[test]
+    fn public_errors_implement_error() {
+        assert_error::<SignatureError>();
+        assert_error::<KeyError>();
+        assert_error::<DecodeError>();
+        #[cfg(feature = "serde")]
+        assert_error::<crate::PickleError>();
+        #[cfg(feature = "libolm-compat")]
+        assert_error::<crate::LibolmPickleError>();
+
+        assert_error::<crate::megolm::DecryptionError>();
+        assert_error::<crate::megolm::EncryptError>();
+        assert_error::<crate::megolm::SessionKeyDecodeError>();
+
+        assert_error::<crate::olm::DecryptionError>();
+        assert_error::<crate::olm::SessionCreationError>();
+
+        assert_error::<crate::sas::SasError>();
+    }
+}
@@ -12,19 +12,36 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+#[cfg(feature = "serde")]
 use serde::{Deserialize, Serialize};
+use thiserror::Error;
 
+#[cfg(feature = "serde")]
+use super::default_config;
 use super::{
-    default_config, message::MegolmMessage, ratchet::Ratchet, session_config::Version,
-    session_keys::SessionKey, SessionConfig,
+    message::MegolmMessage, ratchet::Ratchet, session_config::Version, session_keys::SessionKey,
+    InboundGroupSession, SessionConfig,
 };
+use crate::types::Ed25519Keypair;
+#[cfg(feature = "serde")]
 use crate::{
-    cipher::Cipher,
-    types::Ed25519Keypair,
     utilities::{pickle, unpickle},
     PickleError,
 };
 
+/// Error type for Megolm-based encryption failures.
+#[derive(Debug, Error)]
+#[non_exhaustive]
+pub enum EncryptError {
+    /// The message index of the group session reached its maximum value,
+    /// advancing the ratchet any further would wrap the index around and
+    /// reuse message keys. The session needs to be replaced with a new one.
+    #[error("The group session is exhausted, its message index reached {0}")]
+    SessionExhausted(u32),
+}
+
 /// A Megolm group session represents a single sending participant in an
 /// encrypted group communication context containing multiple receiving parties.
 ///
@@ -43,6 +60,18 @@ pub struct GroupSession {
     ratchet: Ratchet,
     signing_key: Ed25519Keypair,
     config: SessionConfig,
+    creation_time: Option<u64>,
+}
+
+/// The current time in seconds since the UNIX epoch, `None` on platforms
+/// without a system clock.
+fn unix_timestamp() -> Option<u64> {
+    // `SystemTime::now()` panics on `wasm32-unknown-unknown`.
+    if cfg!(all(target_arch = "wasm32", target_os = "unknown")) {
+        None
+    } else {
+        SystemTime::now().duration_since(UNIX_EPOCH).ok().map(|d| d.as_secs())
+    }
 }
 
 impl Default for GroupSession {
@@ -56,7 +85,7 @@ impl GroupSession {
     /// key pair.
     pub fn new(config: SessionConfig) -> Self {
         let signing_key = Ed25519Keypair::new();
-        Self { signing_key, ratchet: Ratchet::new(), config }
+        Self { signing_key, ratchet: Ratchet::new(), config, creation_time: unix_timestamp() }
     }
 
     /// Returns the globally unique session ID, in base64-encoded form.
@@ -80,12 +109,56 @@ impl GroupSession {
         self.config
     }
 
+    /// Get the raw bytes of the ratchet of the group session.
+    ///
+    /// This is only meant for generating test vectors and forensic debugging.
+    ///
+    /// **Warning**: The bytes are equivalent to the session key at the current
+    /// message index, whoever knows them can decrypt every message this
+    /// session will encrypt from now on. The returned buffer is zeroized when
+    /// it's dropped.
+    #[cfg(feature = "dangerous-export")]
+    pub fn __dangerous_ratchet_bytes(&self) -> zeroize::Zeroizing<Vec<u8>> {
+        self.ratchet.to_zeroizing_bytes()
+    }
+
+    /// Check if the group session should be replaced with a new one.
+    ///
+    /// Returns `true` once `max_messages` messages have been encrypted with the
+    /// group session or once the session is older than `max_age`. The Matrix
+    /// spec suggests rotating outbound sessions after 100 messages or a week.
+    ///
+    /// The age of the session is only known if it was created with this
+    /// version of vodozemac on a platform with a system clock, sessions
+    /// restored from libolm pickles or older pickles, as well as sessions
+    /// created on `wasm32-unknown-unknown`, are only rotated based on the
+    /// message count.
+    pub fn should_rotate(&self, max_messages: u32, max_age: Duration) -> bool {
+        let too_old = match (self.creation_time, unix_timestamp()) {
+            (Some(created), Some(now)) => now.saturating_sub(created) >= max_age.as_secs(),
+            _ => false,
+        };
+
+        self.message_index() >= max_messages || too_old
+    }
+
     /// Encrypt the `plaintext` with the group session.
     ///
     /// The resulting ciphertext is MAC-ed, then signed with the group session's
     /// Ed25519 key pair and finally base64-encoded.
-    pub fn encrypt(&mut self, plaintext: impl AsRef<[u8]>) -> MegolmMessage {
-        let cipher = Cipher::new_megolm(self.ratchet.as_bytes());
+    ///
+    /// The returned [`MegolmMessage`] records the index it was encrypted at,
+    /// see [`MegolmMessage::message_index()`]. The group session's own message
+    /// index is advanced by one afterwards.
+    ///
+    /// Fails with [`EncryptError::SessionExhausted`] once the message index
+    /// can't be advanced anymore without overflowing.
+    pub fn encrypt(&mut self, plaintext: impl AsRef<[u8]>) -> Result<MegolmMessage, EncryptError> {
+        if self.message_index() == u32::MAX {
+            return Err(EncryptError::SessionExhausted(self.message_index()));
+        }
+
+        let cipher = self.config.cipher(&self.ratchet);
 
         let message = match self.config.version {
             Version::V1 => MegolmMessage::encrypt_truncated_mac(
@@ -100,11 +173,18 @@ impl GroupSession {
                 &self.signing_key,
                 plaintext.as_ref(),
             ),
+            #[cfg(feature = "gcm")]
+            Version::Gcm => MegolmMessage::encrypt_gcm(
+                self.message_index(),
+                &cipher,
+                &self.signing_key,
+                plaintext.as_ref(),
+            ),
         };
 
         self.ratchet.advance();
 
-        message
+        Ok(message)
     }
 
     /// Export the group session into a session key.
@@ -125,18 +205,35 @@ impl GroupSession {
         session_key
     }
 
+    /// Create an [`InboundGroupSession`] which can decrypt the messages this
+    /// group session encrypts, starting at the current message index.
+    ///
+    /// This is useful to decrypt our own messages. Messages that were
+    /// encrypted before the inbound session was created can't be decrypted by
+    /// it, so it should be created before encrypting, usually right after the
+    /// group session itself.
+    ///
+    /// This is the same as the `From<&GroupSession>` implementation of
+    /// [`InboundGroupSession`].
+    pub fn as_inbound(&self) -> InboundGroupSession {
+        InboundGroupSession::new(&self.session_key(), self.config)
+    }
+
     /// Convert the group session into a struct which implements
     /// [`serde::Serialize`] and [`serde::Deserialize`].
+    #[cfg(feature = "serde")]
     pub fn pickle(&self) -> GroupSessionPickle {
         GroupSessionPickle {
             ratchet: self.ratchet.clone(),
             signing_key: self.signing_key.clone(),
             config: self.config,
+            creation_time: self.creation_time,
         }
     }
 
     /// Restore a [`GroupSession`] from a previously saved
     /// [`GroupSessionPickle`].
+    #[cfg(feature = "serde")]
     pub fn from_pickle(pickle: GroupSessionPickle) -> Self {
         pickle.into()
     }
@@ -173,7 +270,12 @@ impl GroupSession {
                 let signing_key =
                     Ed25519Keypair::from_expanded_key(&pickle.ed25519_keypair.private_key)?;
 
-                Ok(Self { ratchet, signing_key, config: SessionConfig::version_1() })
+                Ok(Self {
+                    ratchet,
+                    signing_key,
+                    config: SessionConfig::version_1(),
+                    creation_time: None,
+                })
             }
         }
 
@@ -185,14 +287,18 @@ impl GroupSession {
 
 /// A format suitable for serialization which implements [`serde::Serialize`]
 /// and [`serde::Deserialize`]. Obtainable by calling [`GroupSession::pickle`].
+#[cfg(feature = "serde")]
 #[derive(Serialize, Deserialize)]
 pub struct GroupSessionPickle {
     ratchet: Ratchet,
     signing_key: Ed25519Keypair,
     #[serde(default = "default_config")]
     config: SessionConfig,
+    #[serde(default)]
+    creation_time: Option<u64>,
 }
 
+#[cfg(feature = "serde")]
 impl GroupSessionPickle {
     /// Serialize and encrypt the pickle using the given key.
     ///
@@ -210,8 +316,70 @@ impl GroupSessionPickle {
     }
 }
 
+#[cfg(feature = "serde")]
 impl From<GroupSessionPickle> for GroupSession {
     fn from(pickle: GroupSessionPickle) -> Self {
-        Self { ratchet: pickle.ratchet, signing_key: pickle.signing_key, config: pickle.config }
+        Self {
+            ratchet: pickle.ratchet,
+            signing_key: pickle.signing_key,
+            config: pickle.config,
+            creation_time: pickle.creation_time,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{EncryptError, GroupSession};
+    use crate::megolm::{ratchet::Ratchet, InboundGroupSession};
+
+    #[test]
+    fn encrypting_stops_before_the_index_overflows() {
+        let mut session = GroupSession::new(Default::default());
+        session.ratchet = Ratchet::from_bytes(Box::new(*session.ratchet.as_bytes()), u32::MAX - 1);
+
+        let mut inbound = InboundGroupSession::new(&session.session_key(), Default::default());
+
+        let message =
+            session.encrypt("It's a secret to everybody").expect("The last index is usable");
+        assert_eq!(message.message_index(), u32::MAX - 1);
+        assert_eq!(session.message_index(), u32::MAX);
+        inbound.decrypt(&message).expect("We should be able to decrypt the last message");
+
+        let error = session.encrypt("Another secret").expect_err("The session should be exhausted");
+        assert!(matches!(error, EncryptError::SessionExhausted(u32::MAX)));
+        assert_eq!(session.message_index(), u32::MAX);
+    }
+
+    #[test]
+    fn decrypting_our_own_messages() {
+        let mut session = GroupSession::new(Default::default());
+        session.encrypt("Before").expect("The session should be able to encrypt");
+
+        let mut inbound = session.as_inbound();
+        assert_eq!(inbound.first_known_index(), 1);
+        assert_eq!(inbound.session_id(), session.session_id());
+        assert!(inbound.is_verified());
+
+        for plaintext in ["First", "Second", "Third"] {
+            let message =
+                session.encrypt(plaintext).expect("The session should be able to encrypt");
+            let decrypted =
+                inbound.decrypt(&message).expect("We should be able to decrypt our own message");
+
+            assert_eq!(decrypted.plaintext, plaintext.as_bytes());
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "dangerous-export")]
+    fn dangerous_ratchet_bytes_match_the_session_key() {
+        let mut session = GroupSession::new(Default::default());
+        session
+            .encrypt("It's a secret to everybody")
+            .expect("The session should be able to encrypt");
+
+        let bytes = session.__dangerous_ratchet_bytes();
+        assert_eq!(bytes.as_slice(), session.session_key().session_key.ratchet.as_ref());
     }
 }
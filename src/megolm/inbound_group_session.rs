@@ -12,27 +12,35 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use std::cmp::Ordering;
+use std::{cmp::Ordering, collections::BTreeMap};
 
 use aes::cipher::block_padding::UnpadError;
+use arrayvec::ArrayVec;
 use hmac::digest::MacError;
+#[cfg(feature = "serde")]
 use serde::{Deserialize, Serialize};
 use subtle::ConstantTimeEq;
 use thiserror::Error;
 use zeroize::Zeroize;
 
+#[cfg(feature = "serde")]
+use super::default_config;
 use super::{
-    default_config,
-    message::MegolmMessage,
+    message::{MegolmMac, MegolmMessage},
     ratchet::Ratchet,
     session_config::Version,
-    session_keys::{ExportedSessionKey, SessionKey},
-    GroupSession, SessionConfig,
+    session_keys::{ExportedSessionKey, SessionKey, SessionKeyDecodeError},
+    verify_signature, GroupSession, SessionConfig,
 };
 use crate::{
     cipher::{Cipher, Mac, MessageMac},
     types::{Ed25519PublicKey, SignatureError},
-    utilities::{base64_encode, pickle, unpickle},
+    utilities::base64_encode,
+    DecodeError,
+};
+#[cfg(feature = "serde")]
+use crate::{
+    utilities::{pickle, unpickle},
     PickleError,
 };
 
@@ -54,10 +62,17 @@ pub enum SessionOrdering {
 
 /// Error type for Megolm-based decryption failuers.
 #[derive(Debug, Error)]
+#[non_exhaustive]
 pub enum DecryptionError {
-    /// The signature on the message was invalid.
-    #[error("The signature on the message was invalid: {0}")]
-    Signature(#[from] SignatureError),
+    /// The signature on the message couldn't be verified using the signing
+    /// key of the session. The signing key is public and safe to log.
+    ///
+    /// Megolm messages don't contain the ID of the session that encrypted
+    /// them, a message that belongs to a different session fails in the same
+    /// way as a corrupted one. Use [`decrypt_any()`] to find the matching
+    /// session out of several candidates.
+    #[error("The signature on the message couldn't be verified using the signing key {0}: {1}")]
+    Signature(Box<Ed25519PublicKey>, #[source] SignatureError),
 
     /// The message authentication code of the message was invalid.
     #[error("Failed decrypting Megolm message, invalid MAC: {0}")]
@@ -80,25 +95,136 @@ pub enum DecryptionError {
         first known index {0}, index of the message {1}"
     )]
     UnknownMessageIndex(u32, u32),
+
+    /// The message couldn't be decoded.
+    #[error("The message couldn't be decoded: {0}")]
+    Decode(#[from] DecodeError),
+
+    /// None of the given sessions was used to encrypt the message.
+    #[error("None of the given sessions was used to encrypt the message")]
+    UnknownSession,
+
+    /// The plaintext of the message would be larger than the maximum size
+    /// configured using [`InboundGroupSession::set_max_plaintext_size()`].
+    #[error("The plaintext of the message is too large, expected at most {0} bytes, got {1}")]
+    PlaintextTooLarge(usize, usize),
 }
 
-#[derive(Deserialize)]
-#[serde(try_from = "InboundGroupSessionPickle")]
+#[cfg_attr(feature = "serde", derive(Deserialize))]
+#[cfg_attr(feature = "serde", serde(try_from = "InboundGroupSessionPickle"))]
 pub struct InboundGroupSession {
     initial_ratchet: Ratchet,
     latest_ratchet: Ratchet,
     signing_key: Ed25519PublicKey,
     signing_key_verified: bool,
     config: SessionConfig,
+    /// Copies of the ratchet at power-of-two message indices, `None` if
+    /// checkpointing is disabled. See
+    /// [`InboundGroupSession::with_checkpointing()`].
+    checkpoints: Option<BTreeMap<u32, Ratchet>>,
+    /// The maximal size of a plaintext this session will decrypt, see
+    /// [`InboundGroupSession::set_max_plaintext_size()`].
+    max_plaintext_size: Option<usize>,
+    /// The highest message index this session has decrypted, used to report
+    /// the number of skipped messages.
+    highest_decrypted_index: Option<u32>,
+}
+
+impl Clone for InboundGroupSession {
+    /// Create a deep copy of the session.
+    ///
+    /// The copy owns its own ratchets, decrypting messages with either of the
+    /// sessions won't advance the other one.
+    fn clone(&self) -> Self {
+        Self {
+            initial_ratchet: self.initial_ratchet.clone(),
+            latest_ratchet: self.latest_ratchet.clone(),
+            signing_key: self.signing_key,
+            signing_key_verified: self.signing_key_verified,
+            config: self.config,
+            checkpoints: self.checkpoints.clone(),
+            max_plaintext_size: self.max_plaintext_size,
+            highest_decrypted_index: self.highest_decrypted_index,
+        }
+    }
 }
 
+/// Metadata of a Megolm message, obtained without decrypting the message.
+///
+/// Returned by [`InboundGroupSession::inspect()`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MessageMetadata {
+    /// The message index that was used to encrypt the message.
+    pub message_index: u32,
+    /// The signing key of the session the message was checked against.
+    pub signing_key: Ed25519PublicKey,
+    /// Was the message signed by the signing key of the session.
+    pub signature_valid: bool,
+}
+
+/// A successfully decrypted Megolm message.
 #[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
 pub struct DecryptedMessage {
+    /// The plaintext of the message.
     pub plaintext: Vec<u8>,
+    /// The message index that was used to encrypt the message.
     pub message_index: u32,
+    /// The number of message indices the session skipped over to reach this
+    /// message.
+    ///
+    /// This is the gap between the highest message index the session has
+    /// decrypted and the message index, i.e. if the highest decrypted message
+    /// had the index `N - 5` and this message has the index `N`, four messages
+    /// were skipped. A session that hasn't decrypted anything yet counts from
+    /// its first known index. Messages at or below the highest decrypted index
+    /// always report zero skipped messages.
+    pub skipped: u32,
+    /// The message authentication code that was verified before the message
+    /// was decrypted.
+    ///
+    /// This is the MAC as it was found in the message, i.e. truncated to
+    /// eight bytes for sessions using [`SessionConfig::version_1()`]. It can
+    /// be used to tell apart messages that share a message index, e.g. to
+    /// deduplicate messages that were received more than once.
+    pub mac: ArrayVec<u8, { Mac::LENGTH }>,
+    /// What, besides the MAC, could be verified about the message.
+    pub verification: VerificationResult,
+}
+
+/// The authenticity checks of a successfully decrypted Megolm message.
+///
+/// The MAC of the message is always checked, a message with an invalid MAC is
+/// never decrypted. The remaining checks tie the message to a specific sender
+/// and can be weaker depending on how the session was obtained.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VerificationResult {
+    /// Was the signing key of the session verified when the session was
+    /// created, see [`InboundGroupSession::is_verified()`].
+    pub signing_key_verified: bool,
+    /// Was the signature of the message checked against the signing key of
+    /// the session. This is only `false` if signature checks were disabled
+    /// using the `no-verify` feature.
+    pub signature_verified: bool,
+}
+
+/// The result of importing a single session using
+/// [`InboundGroupSession::import_batch`].
+pub struct ImportResult {
+    /// The imported session.
+    pub session: InboundGroupSession,
+    /// Was the signing key of the session verified at import time.
+    ///
+    /// This is only the case if the session was imported from a signed
+    /// [`SessionKey`].
+    pub verified: bool,
 }
 
 impl InboundGroupSession {
+    /// Create a new `InboundGroupSession` from a signed [`SessionKey`].
+    ///
+    /// The signature of the session key was checked when it was decoded, so
+    /// the signing key of the session is considered to be verified.
     pub fn new(key: &SessionKey, session_config: SessionConfig) -> Self {
         let initial_ratchet =
             Ratchet::from_bytes(key.session_key.ratchet.clone(), key.session_key.ratchet_index);
@@ -110,9 +236,16 @@ impl InboundGroupSession {
             signing_key: key.session_key.signing_key,
             signing_key_verified: true,
             config: session_config,
+            checkpoints: None,
+            max_plaintext_size: None,
+            highest_decrypted_index: None,
         }
     }
 
+    /// Import an `InboundGroupSession` from an [`ExportedSessionKey`].
+    ///
+    /// Exported session keys don't carry a signature, the signing key of the
+    /// session is thus unverified.
     pub fn import(session_key: &ExportedSessionKey, session_config: SessionConfig) -> Self {
         let initial_ratchet =
             Ratchet::from_bytes(session_key.ratchet.clone(), session_key.ratchet_index);
@@ -124,13 +257,90 @@ impl InboundGroupSession {
             signing_key: session_key.signing_key,
             signing_key_verified: false,
             config: session_config,
+            checkpoints: None,
+            max_plaintext_size: None,
+            highest_decrypted_index: None,
         }
     }
 
+    /// Import a batch of base64 encoded session keys.
+    ///
+    /// Each key can either be a [`SessionKey`], in which case its signature
+    /// is checked and the session is considered to be verified, or an
+    /// [`ExportedSessionKey`], in which case the session is imported as
+    /// unverified.
+    ///
+    /// The two formats are told apart by their version byte, see
+    /// [`SessionKey::VERSION`] and [`ExportedSessionKey::VERSION`]. Keys with
+    /// any other version are rejected with a
+    /// [`SessionKeyDecodeError::Version`] error.
+    ///
+    /// Returns one result per given key, in the same order as the keys.
+    pub fn import_batch<'a>(
+        keys: impl IntoIterator<Item = &'a str>,
+        session_config: SessionConfig,
+    ) -> Vec<Result<ImportResult, SessionKeyDecodeError>> {
+        keys.into_iter()
+            .map(|key| {
+                let session = match SessionKey::from_base64(key) {
+                    Ok(session_key) => Self::new(&session_key, session_config),
+                    Err(SessionKeyDecodeError::Version(_, ExportedSessionKey::VERSION)) => {
+                        Self::import(&ExportedSessionKey::from_base64(key)?, session_config)
+                    }
+                    Err(e) => return Err(e),
+                };
+
+                Ok(ImportResult { verified: session.is_verified(), session })
+            })
+            .collect()
+    }
+
+    /// Returns the globally unique session ID, in base64-encoded form.
+    ///
+    /// The session ID is the public part of the Ed25519 signing key of the
+    /// session, it's the same ID the sending [`GroupSession`] reports and the
+    /// key Matrix clients use to store and look up their sessions.
+    ///
+    /// Two `InboundGroupSession`s with the same session ID aren't necessarily
+    /// the same session, anybody can create a session key containing a known
+    /// signing key. They are only the same session if their ratchets are
+    /// [connected] as well, [`InboundGroupSession::compare()`] checks both.
+    ///
+    /// [connected]: InboundGroupSession::connected
     pub fn session_id(&self) -> String {
         base64_encode(self.signing_key.as_bytes())
     }
 
+    /// Has the signing key of this session been verified.
+    ///
+    /// Sessions created from a [`SessionKey`] have a verified signing key,
+    /// sessions imported from an [`ExportedSessionKey`] don't, unless they
+    /// were [merged] with a verified session.
+    ///
+    /// [merged]: InboundGroupSession::merge
+    pub fn is_verified(&self) -> bool {
+        self.signing_key_verified
+    }
+
+    /// Mark the signing key of this session as verified if it matches the
+    /// given, trusted, Ed25519 key.
+    ///
+    /// Sessions imported from an [`ExportedSessionKey`] or a libolm pickle
+    /// can't prove that they belong to their signing key. Once the signing key
+    /// of the sender has been verified in some other way, this method can be
+    /// used to upgrade the trust of the session without importing it again.
+    ///
+    /// Returns `true` if the keys match and the session is now verified, a
+    /// mismatched key leaves the session untouched.
+    pub fn mark_verified_if_matches(&mut self, trusted_key: &Ed25519PublicKey) -> bool {
+        if self.signing_key == *trusted_key {
+            self.signing_key_verified = true;
+            true
+        } else {
+            false
+        }
+    }
+
     /// Check if two `InboundGroupSession`s are the same.
     ///
     /// An `InboundGroupSession` could be received multiple times with varying
@@ -187,6 +397,20 @@ impl InboundGroupSession {
         }
     }
 
+    /// Check if this session can decrypt more messages than the given other
+    /// session, i.e. if it has the same signing key and a lower first known
+    /// index.
+    ///
+    /// Sessions with different signing keys can't be compared, this returns
+    /// `false` for them in both directions. Unlike
+    /// [`InboundGroupSession::compare()`], the ratchets aren't checked to be
+    /// connected, this only needs a shared reference and is cheap enough to
+    /// be used as a sort criterion.
+    pub fn more_capable_than(&self, other: &InboundGroupSession) -> bool {
+        self.signing_key == other.signing_key
+            && self.first_known_index() < other.first_known_index()
+    }
+
     /// Merge the session with the given other session, picking the best parts
     /// from each of them.
     ///
@@ -238,13 +462,62 @@ impl InboundGroupSession {
             signing_key: self.signing_key,
             signing_key_verified: self.signing_key_verified || other.signing_key_verified,
             config: self.config,
+            checkpoints: self.checkpoints.as_ref().map(|_| BTreeMap::new()),
+            max_plaintext_size: self.max_plaintext_size,
+            highest_decrypted_index: self
+                .highest_decrypted_index
+                .max(other.highest_decrypted_index),
         })
     }
 
+    /// Enable or disable checkpointing of the ratchet.
+    ///
+    /// The session normally only remembers the ratchet of the most recently
+    /// decrypted message. Decrypting a message with a lower index than that
+    /// has to re-derive the ratchet starting from the first known index. If
+    /// messages are decrypted in an arbitrary order, e.g. when events are
+    /// replayed from a store, this happens over and over again.
+    ///
+    /// With checkpointing enabled the session additionally keeps a copy of the
+    /// ratchet at every power-of-two message index it passes, and starts from
+    /// the closest checkpoint instead. This costs up to 32 additional ratchets
+    /// of memory.
+    ///
+    /// Checkpoints before the first known index are discarded when the session
+    /// is advanced using [`InboundGroupSession::advance_to()`]. Checkpoints
+    /// are not part of the pickle of the session.
+    pub fn with_checkpointing(mut self, enabled: bool) -> Self {
+        self.checkpoints = enabled.then(BTreeMap::new);
+        self
+    }
+
+    /// Limit the size of the plaintexts this session will decrypt.
+    ///
+    /// Decrypting a message whose plaintext is larger than `size` bytes fails
+    /// with a [`DecryptionError::PlaintextTooLarge`] error. Messages whose
+    /// ciphertext is too long to fit the limit are rejected before any memory
+    /// for the plaintext is allocated. This protects against memory exhaustion
+    /// caused by a compromised sender.
+    ///
+    /// The limit isn't part of the pickle of the session.
+    pub fn set_max_plaintext_size(&mut self, size: usize) {
+        self.max_plaintext_size = Some(size);
+    }
+
     pub fn first_known_index(&self) -> u32 {
         self.initial_ratchet.index()
     }
 
+    /// Get the message index of the most recently used ratchet.
+    ///
+    /// The session keeps a working copy of the ratchet which is advanced as
+    /// messages get decrypted. Together with
+    /// [`InboundGroupSession::first_known_index()`] this tells us how far the
+    /// session has moved forward.
+    pub fn latest_ratchet_index(&self) -> u32 {
+        self.latest_ratchet.index()
+    }
+
     /// Permanently advance the session to the given index.
     ///
     /// This will remove the ability to decrypt messages that were encrypted
@@ -256,6 +529,10 @@ impl InboundGroupSession {
         if self.first_known_index() < index {
             self.initial_ratchet.advance_to(index);
 
+            if let Some(checkpoints) = &mut self.checkpoints {
+                *checkpoints = checkpoints.split_off(&index);
+            }
+
             if self.latest_ratchet.index() < index {
                 self.latest_ratchet = self.initial_ratchet.clone();
             }
@@ -271,66 +548,266 @@ impl InboundGroupSession {
     #[cfg(feature = "low-level-api")]
     pub fn get_cipher_at(&self, message_index: u32) -> Option<Cipher> {
         if self.initial_ratchet.index() <= message_index {
-            let mut ratchet = self.initial_ratchet.clone();
-            if self.initial_ratchet.index() < message_index {
-                ratchet.advance_to(message_index);
-            }
-            Some(Cipher::new_megolm(ratchet.as_bytes()))
+            let ratchet = self.initial_ratchet.advanced_to(message_index);
+            Some(self.config.cipher(&ratchet))
         } else {
             None
         }
     }
 
+    /// Find the ratchet for the given message index.
+    ///
+    /// The session always keeps the invariant that the latest ratchet is at
+    /// the same or a higher index than the initial ratchet. Depending on where
+    /// the message index falls, relative to those two, we either:
+    ///
+    /// * return `None` if the index is before the initial ratchet, ratchets
+    ///   can't go backwards.
+    /// * return the initial ratchet if the index matches it.
+    /// * return the latest ratchet if the index matches it.
+    /// * advance the latest ratchet if the index is past it.
+    /// * restart the latest ratchet from the initial one, or from the closest
+    ///   checkpoint if checkpointing is enabled, and advance it, if the index
+    ///   is between the two.
     fn find_ratchet(&mut self, message_index: u32) -> Option<&Ratchet> {
-        if self.initial_ratchet.index() == message_index {
-            Some(&self.initial_ratchet)
-        } else if self.latest_ratchet.index() == message_index {
-            Some(&self.latest_ratchet)
-        } else if self.latest_ratchet.index() < message_index {
-            self.latest_ratchet.advance_to(message_index);
-            Some(&self.latest_ratchet)
-        } else if self.initial_ratchet.index() < message_index {
-            self.latest_ratchet = self.initial_ratchet.clone();
-            self.latest_ratchet.advance_to(message_index);
-            Some(&self.latest_ratchet)
-        } else {
-            None
+        let initial_index = self.initial_ratchet.index();
+        let latest_index = self.latest_ratchet.index();
+
+        match (message_index.cmp(&initial_index), message_index.cmp(&latest_index)) {
+            (Ordering::Less, _) => None,
+            (Ordering::Equal, _) => Some(&self.initial_ratchet),
+            (Ordering::Greater, Ordering::Equal) => Some(&self.latest_ratchet),
+            (Ordering::Greater, Ordering::Greater) => {
+                Self::advance_ratchet(
+                    &mut self.latest_ratchet,
+                    &mut self.checkpoints,
+                    message_index,
+                );
+                Some(&self.latest_ratchet)
+            }
+            (Ordering::Greater, Ordering::Less) => {
+                let start = self
+                    .checkpoints
+                    .as_ref()
+                    .and_then(|c| c.range(..=message_index).next_back())
+                    .map(|(_, ratchet)| ratchet)
+                    .filter(|ratchet| ratchet.index() > initial_index)
+                    .unwrap_or(&self.initial_ratchet);
+
+                self.latest_ratchet = start.clone();
+                Self::advance_ratchet(
+                    &mut self.latest_ratchet,
+                    &mut self.checkpoints,
+                    message_index,
+                );
+
+                Some(&self.latest_ratchet)
+            }
+        }
+    }
+
+    /// Advance the ratchet to the given index, storing a copy of the ratchet
+    /// at every power-of-two index along the way if checkpointing is enabled.
+    fn advance_ratchet(
+        ratchet: &mut Ratchet,
+        checkpoints: &mut Option<BTreeMap<u32, Ratchet>>,
+        index: u32,
+    ) {
+        if let Some(checkpoints) = checkpoints {
+            let mut next = ratchet.index().checked_add(1).and_then(u32::checked_next_power_of_two);
+
+            while let Some(checkpoint) = next.filter(|&c| c <= index) {
+                ratchet.advance_to(checkpoint);
+                checkpoints.entry(checkpoint).or_insert_with(|| ratchet.clone());
+
+                next = checkpoint.checked_mul(2);
+            }
         }
+
+        ratchet.advance_to(index);
+    }
+
+    /// Get the [`Cipher`] for the given message index.
+    fn cipher_at(&mut self, message_index: u32) -> Option<Cipher> {
+        let config = self.config;
+        self.find_ratchet(message_index).map(|r| config.cipher(r))
     }
 
-    fn verify_mac(&self, cipher: &Cipher, message: &MegolmMessage) -> Result<(), DecryptionError> {
+    /// Verify the MAC of the message and decrypt its ciphertext into the given
+    /// buffer.
+    fn decrypt_ciphertext(
+        &self,
+        cipher: &Cipher,
+        message: &MegolmMessage,
+        out: &mut Vec<u8>,
+    ) -> Result<(), DecryptionError> {
+        let invalid_length =
+            || DecryptionError::InvalidMACLength(self.config.mac_length(), message.mac().len());
+
         match self.config.version {
             Version::V1 => {
-                if let MessageMac::Truncated(m) = &message.mac {
-                    Ok(cipher.verify_truncated_mac(&message.to_mac_bytes(), m)?)
+                if let MegolmMac::Hmac(MessageMac::Truncated(m)) = &message.mac {
+                    cipher.verify_truncated_mac(&message.to_mac_bytes(), m)?;
+                    Ok(cipher.decrypt_into(&message.ciphertext, out)?)
                 } else {
-                    Err(DecryptionError::InvalidMACLength(Mac::TRUNCATED_LEN, Mac::LENGTH))
+                    Err(invalid_length())
                 }
             }
             Version::V2 => {
-                if let MessageMac::Full(m) = &message.mac {
-                    Ok(cipher.verify_mac(&message.to_mac_bytes(), m)?)
+                if let MegolmMac::Hmac(MessageMac::Full(m)) = &message.mac {
+                    cipher.verify_mac(&message.to_mac_bytes(), m)?;
+                    Ok(cipher.decrypt_into(&message.ciphertext, out)?)
+                } else {
+                    Err(invalid_length())
+                }
+            }
+            #[cfg(feature = "gcm")]
+            Version::Gcm => {
+                if let MegolmMac::Gcm(tag) = &message.mac {
+                    let nonce = MegolmMessage::gcm_nonce(message.message_index);
+                    Ok(cipher.decrypt_gcm_into(&nonce, &message.ciphertext, tag, out)?)
                 } else {
-                    Err(DecryptionError::InvalidMACLength(Mac::LENGTH, Mac::TRUNCATED_LEN))
+                    Err(invalid_length())
                 }
             }
         }
     }
 
+    /// Decrypt the given [`MegolmMessage`].
+    ///
+    /// The signature of the message is checked against the signing key of the
+    /// session first. This is also the only check that ties the message to
+    /// the session, decrypting a message of a different session fails with a
+    /// [`DecryptionError::Signature`] error.
     pub fn decrypt(
         &mut self,
         message: &MegolmMessage,
     ) -> Result<DecryptedMessage, DecryptionError> {
-        self.signing_key.verify(&message.to_signature_bytes(), &message.signature)?;
+        verify_signature(&self.signing_key, &message.to_signature_bytes(), &message.signature)
+            .map_err(|e| DecryptionError::Signature(Box::new(self.signing_key), e))?;
+
+        self.decrypt_checked_message(message, !cfg!(feature = "no-verify"))
+    }
+
+    /// Decrypt a base64 encoded Megolm message into the given buffer,
+    /// returning the message index.
+    ///
+    /// The buffer is cleared and its capacity reused, which avoids allocating
+    /// a new plaintext for every message. On failure the buffer is left empty.
+    /// The checks are the same as the ones [`InboundGroupSession::decrypt()`]
+    /// performs.
+    pub fn decrypt_into(
+        &mut self,
+        ciphertext: &str,
+        out: &mut Vec<u8>,
+    ) -> Result<u32, DecryptionError> {
+        out.clear();
 
-        if let Some(ratchet) = self.find_ratchet(message.message_index) {
-            let cipher = Cipher::new_megolm(ratchet.as_bytes());
+        let message = MegolmMessage::from_base64(ciphertext)?;
 
-            self.verify_mac(&cipher, message)?;
+        verify_signature(&self.signing_key, &message.to_signature_bytes(), &message.signature)
+            .map_err(|e| DecryptionError::Signature(Box::new(self.signing_key), e))?;
 
-            let plaintext = cipher.decrypt(&message.ciphertext)?;
+        self.decrypt_message_into(&message, out)?;
 
-            Ok(DecryptedMessage { plaintext, message_index: message.message_index })
+        Ok(message.message_index)
+    }
+
+    /// Decode and decrypt a Megolm message given as raw bytes.
+    ///
+    /// The bytes need to follow the format described in
+    /// [`MegolmMessage::to_bytes()`]. This is useful for transports that carry
+    /// the message as binary, it saves a round-trip through base64.
+    pub fn decrypt_bytes(&mut self, decoded: &[u8]) -> Result<DecryptedMessage, DecryptionError> {
+        let message = MegolmMessage::from_bytes(decoded)?;
+        self.decrypt(&message)
+    }
+
+    /// Parse the given base64 encoded Megolm message and check its signature,
+    /// without decrypting it.
+    ///
+    /// Unlike [`InboundGroupSession::decrypt()`], this works for messages whose
+    /// index the session hasn't reached yet, or has already ratcheted past.
+    /// This can be used to decide if a message should be queued until a
+    /// better session arrives.
+    ///
+    /// **Note**: The signature is always checked, even if the `no-verify`
+    /// feature is enabled, since reporting it is the point of this method.
+    pub fn inspect(&self, ciphertext: &str) -> Result<MessageMetadata, DecryptionError> {
+        let message = MegolmMessage::from_base64(ciphertext)?;
+        let signature_valid =
+            self.signing_key.verify(&message.to_signature_bytes(), &message.signature).is_ok();
+
+        Ok(MessageMetadata {
+            message_index: message.message_index,
+            signing_key: self.signing_key,
+            signature_valid,
+        })
+    }
+
+    /// Decrypt a message whose signature was already checked by the caller.
+    ///
+    /// `signature_verified` is only recorded in the returned
+    /// [`VerificationResult`], it's `false` when the check was skipped.
+    fn decrypt_checked_message(
+        &mut self,
+        message: &MegolmMessage,
+        signature_verified: bool,
+    ) -> Result<DecryptedMessage, DecryptionError> {
+        let mut plaintext = Vec::new();
+        let skipped = self.decrypt_message_into(message, &mut plaintext)?;
+
+        let verification = VerificationResult {
+            signing_key_verified: self.signing_key_verified,
+            signature_verified,
+        };
+
+        Ok(DecryptedMessage {
+            plaintext,
+            message_index: message.message_index,
+            skipped,
+            mac: message.mac().iter().copied().collect(),
+            verification,
+        })
+    }
+
+    /// Decrypt a message whose signature has already been checked into the
+    /// given buffer, returning the number of skipped message indices.
+    fn decrypt_message_into(
+        &mut self,
+        message: &MegolmMessage,
+        out: &mut Vec<u8>,
+    ) -> Result<u32, DecryptionError> {
+        if let Some(max_size) = self.max_plaintext_size {
+            // PKCS#7 adds between one and a full block of padding, this is the
+            // smallest plaintext the ciphertext can contain.
+            let min_plaintext_size = message.ciphertext.len().saturating_sub(16);
+
+            if min_plaintext_size > max_size {
+                return Err(DecryptionError::PlaintextTooLarge(max_size, min_plaintext_size));
+            }
+        }
+
+        let next_index = self
+            .highest_decrypted_index
+            .map_or(0, |index| index.saturating_add(1))
+            .max(self.first_known_index());
+        let skipped = message.message_index.saturating_sub(next_index);
+
+        if let Some(cipher) = self.cipher_at(message.message_index) {
+            self.decrypt_ciphertext(&cipher, message, out)?;
+
+            if let Some(max_size) = self.max_plaintext_size.filter(|&m| out.len() > m) {
+                let length = out.len();
+                out.clear();
+
+                return Err(DecryptionError::PlaintextTooLarge(max_size, length));
+            }
+
+            self.highest_decrypted_index =
+                self.highest_decrypted_index.max(Some(message.message_index));
+
+            Ok(skipped)
         } else {
             Err(DecryptionError::UnknownMessageIndex(
                 self.initial_ratchet.index(),
@@ -339,6 +816,48 @@ impl InboundGroupSession {
         }
     }
 
+    /// Get the raw bytes of the latest ratchet of the session.
+    ///
+    /// This is only meant for forensic debugging of live sessions.
+    ///
+    /// **Warning**: The ratchet is the secret state of the session, whoever
+    /// knows it can decrypt every message the session will decrypt from now
+    /// on. Never log, persist, or send these bytes anywhere. The returned
+    /// buffer is zeroized when it's dropped, don't copy the bytes out of it.
+    /// Use [`InboundGroupSession::pickle()`] or
+    /// [`InboundGroupSession::export_at()`] to store or share a session.
+    #[cfg(feature = "dangerous-export")]
+    pub fn __dangerous_ratchet_bytes(&self) -> zeroize::Zeroizing<Vec<u8>> {
+        self.latest_ratchet.to_zeroizing_bytes()
+    }
+
+    /// Lazily derive the message keys for the message indices in the range
+    /// `from..to`.
+    ///
+    /// Indices before the first known index of the session can't be derived
+    /// and are skipped. The session itself isn't advanced.
+    ///
+    /// **Warning**: The keys decrypt every message in the given range. This is
+    /// only meant for debugging tools.
+    #[cfg(feature = "dangerous-export")]
+    pub fn message_keys(
+        &self,
+        from: u32,
+        to: u32,
+    ) -> impl Iterator<Item = (u32, crate::cipher::MessageKeys)> {
+        use super::ratchet::RatchetIter;
+
+        let config = self.config;
+        let from = from.max(self.initial_ratchet.index());
+        let start = (from < to).then(|| self.initial_ratchet.advanced_to(from));
+
+        start
+            .into_iter()
+            .flat_map(RatchetIter::new)
+            .take_while(move |r| r.index() < to)
+            .map(move |ratchet| (ratchet.index(), config.cipher(&ratchet).message_keys()))
+    }
+
     pub fn export_at(&mut self, index: u32) -> Option<ExportedSessionKey> {
         let signing_key = self.signing_key;
 
@@ -351,6 +870,10 @@ impl InboundGroupSession {
 
     /// Convert the inbound group session into a struct which implements
     /// [`serde::Serialize`] and [`serde::Deserialize`].
+    ///
+    /// Only the initial ratchet is stored, the latest ratchet is a cache which
+    /// is re-derived as messages get decrypted after the session is restored.
+    #[cfg(feature = "serde")]
     pub fn pickle(&self) -> InboundGroupSessionPickle {
         InboundGroupSessionPickle {
             initial_ratchet: self.initial_ratchet.clone(),
@@ -362,6 +885,7 @@ impl InboundGroupSession {
 
     /// Restore an [`InboundGroupSession`] from a previously saved
     /// [`InboundGroupSessionPickle`].
+    #[cfg(feature = "serde")]
     pub fn from_pickle(pickle: InboundGroupSessionPickle) -> Self {
         Self::from(pickle)
     }
@@ -371,56 +895,101 @@ impl InboundGroupSession {
         pickle: &str,
         pickle_key: &[u8],
     ) -> Result<Self, crate::LibolmPickleError> {
-        use matrix_pickle::Decode;
-
-        use super::libolm::LibolmRatchetPickle;
+        use super::libolm::LibolmInboundGroupSessionPickle as Pickle;
         use crate::utilities::unpickle_libolm;
 
-        #[derive(Zeroize, Decode)]
-        #[zeroize(drop)]
-        struct Pickle {
-            version: u32,
-            initial_ratchet: LibolmRatchetPickle,
-            latest_ratchet: LibolmRatchetPickle,
-            signing_key: [u8; 32],
-            signing_key_verified: bool,
-        }
+        unpickle_libolm::<Pickle, _>(pickle, pickle_key, Pickle::VERSION)
+    }
 
-        impl TryFrom<Pickle> for InboundGroupSession {
-            type Error = crate::LibolmPickleError;
-
-            fn try_from(pickle: Pickle) -> Result<Self, Self::Error> {
-                // Removing the borrow doesn't work and clippy complains about
-                // this on nightly.
-                #[allow(clippy::needless_borrow)]
-                let initial_ratchet = (&pickle.initial_ratchet).into();
-                #[allow(clippy::needless_borrow)]
-                let latest_ratchet = (&pickle.latest_ratchet).into();
-                let signing_key = Ed25519PublicKey::from_slice(&pickle.signing_key)?;
-                let signing_key_verified = pickle.signing_key_verified;
-
-                Ok(Self {
-                    initial_ratchet,
-                    latest_ratchet,
-                    signing_key,
-                    signing_key_verified,
-                    config: SessionConfig::version_1(),
-                })
-            }
+    /// Pickle the session in the libolm pickle format, encrypted with the
+    /// given pickle key.
+    ///
+    /// This is the inverse of [`InboundGroupSession::from_libolm_pickle()`]
+    /// and can be used to hand a session back to libolm. libolm has no notion
+    /// of a [`SessionConfig`] and only supports [`SessionConfig::version_1()`],
+    /// sessions using any other config return a
+    /// [`LibolmPickleError::UnsupportedSessionConfig`] error.
+    ///
+    /// [`LibolmPickleError::UnsupportedSessionConfig`]: crate::LibolmPickleError::UnsupportedSessionConfig
+    #[cfg(feature = "libolm-compat")]
+    pub fn to_libolm_pickle(&self, pickle_key: &[u8]) -> Result<String, crate::LibolmPickleError> {
+        use super::libolm::LibolmInboundGroupSessionPickle as Pickle;
+        use crate::utilities::pickle_libolm;
+
+        if self.config != SessionConfig::version_1() {
+            return Err(crate::LibolmPickleError::UnsupportedSessionConfig(self.config.version()));
         }
 
-        const PICKLE_VERSION: u32 = 2;
+        let pickle = Pickle {
+            version: Pickle::VERSION,
+            initial_ratchet: (&self.initial_ratchet).into(),
+            latest_ratchet: (&self.latest_ratchet).into(),
+            signing_key: *self.signing_key.as_bytes(),
+            signing_key_verified: self.signing_key_verified,
+        };
 
-        unpickle_libolm::<Pickle, _>(pickle, pickle_key, PICKLE_VERSION)
+        Ok(pickle_libolm(&pickle, pickle_key))
     }
 }
 
+/// Decrypt a base64 encoded Megolm message using the matching session out of
+/// the given list of sessions.
+///
+/// The matching session is the one whose signing key produced the signature
+/// of the message. On success, the position of the session in the list is
+/// returned together with the decrypted message.
+///
+/// **Note**: The signature of the message is always verified, even if the
+/// `no-verify` feature is enabled, since it's the only thing tying the message
+/// to one of the sessions.
+pub fn decrypt_any(
+    sessions: &mut [InboundGroupSession],
+    ciphertext: &str,
+) -> Result<(usize, DecryptedMessage), DecryptionError> {
+    let message = MegolmMessage::from_base64(ciphertext)?;
+    let signature_bytes = message.to_signature_bytes();
+
+    let (index, session) = sessions
+        .iter_mut()
+        .enumerate()
+        .find(|(_, s)| s.signing_key.verify(&signature_bytes, &message.signature).is_ok())
+        .ok_or(DecryptionError::UnknownSession)?;
+
+    Ok((index, session.decrypt_checked_message(&message, true)?))
+}
+
+/// Decrypt base64 encoded Megolm messages of many independent sessions in
+/// parallel.
+///
+/// Each session is paired with the messages that should be decrypted with it.
+/// The sessions are spread over the rayon thread pool, but the messages of a
+/// single session are decrypted on one thread, in the given order. The results
+/// have the same shape as the input, one list of results per session.
+#[cfg(feature = "rayon")]
+pub fn decrypt_batch(
+    sessions: &mut [(InboundGroupSession, Vec<&str>)],
+) -> Vec<Vec<Result<DecryptedMessage, DecryptionError>>> {
+    use rayon::prelude::*;
+
+    sessions
+        .par_iter_mut()
+        .map(|(session, messages)| {
+            messages
+                .iter()
+                .map(|ciphertext| session.decrypt(&MegolmMessage::from_base64(ciphertext)?))
+                .collect()
+        })
+        .collect()
+}
+
 /// A format suitable for serialization which implements [`serde::Serialize`]
 /// and [`serde::Deserialize`]. Obtainable by calling
 /// [`InboundGroupSession::pickle`].
+#[cfg(feature = "serde")]
 #[derive(Serialize, Deserialize)]
 pub struct InboundGroupSessionPickle {
     initial_ratchet: Ratchet,
+    #[serde(with = "crate::types::ed25519_public_key_bytes")]
     signing_key: Ed25519PublicKey,
     #[allow(dead_code)]
     signing_key_verified: bool,
@@ -428,6 +997,7 @@ pub struct InboundGroupSessionPickle {
     config: SessionConfig,
 }
 
+#[cfg(feature = "serde")]
 impl InboundGroupSessionPickle {
     /// Serialize and encrypt the pickle using the given key.
     ///
@@ -445,12 +1015,14 @@ impl InboundGroupSessionPickle {
     }
 }
 
+#[cfg(feature = "serde")]
 impl From<&InboundGroupSession> for InboundGroupSessionPickle {
     fn from(session: &InboundGroupSession) -> Self {
         session.pickle()
     }
 }
 
+#[cfg(feature = "serde")]
 impl From<InboundGroupSessionPickle> for InboundGroupSession {
     fn from(pickle: InboundGroupSessionPickle) -> Self {
         Self {
@@ -459,20 +1031,131 @@ impl From<InboundGroupSessionPickle> for InboundGroupSession {
             signing_key: pickle.signing_key,
             signing_key_verified: pickle.signing_key_verified,
             config: pickle.config,
+            checkpoints: None,
+            max_plaintext_size: None,
+            highest_decrypted_index: None,
         }
     }
 }
 
+#[cfg(feature = "libolm-compat")]
+impl TryFrom<super::libolm::LibolmInboundGroupSessionPickle> for InboundGroupSession {
+    type Error = crate::LibolmPickleError;
+
+    fn try_from(
+        pickle: super::libolm::LibolmInboundGroupSessionPickle,
+    ) -> Result<Self, Self::Error> {
+        // Removing the borrow doesn't work and clippy complains about
+        // this on nightly.
+        #[allow(clippy::needless_borrow)]
+        let initial_ratchet: Ratchet = (&pickle.initial_ratchet).into();
+        #[allow(clippy::needless_borrow)]
+        let latest_ratchet: Ratchet = (&pickle.latest_ratchet).into();
+        let signing_key = Ed25519PublicKey::from_slice(&pickle.signing_key)?;
+        let signing_key_verified = pickle.signing_key_verified;
+        // libolm only advances the latest ratchet when it decrypts a message.
+        let highest_decrypted_index =
+            (latest_ratchet.index() > initial_ratchet.index()).then_some(latest_ratchet.index());
+
+        Ok(Self {
+            initial_ratchet,
+            latest_ratchet,
+            signing_key,
+            signing_key_verified,
+            config: SessionConfig::version_1(),
+            checkpoints: None,
+            max_plaintext_size: None,
+            highest_decrypted_index,
+        })
+    }
+}
+
 impl From<&GroupSession> for InboundGroupSession {
     fn from(session: &GroupSession) -> Self {
-        Self::new(&session.session_key(), session.session_config())
+        session.as_inbound()
+    }
+}
+
+/// Create an `InboundGroupSession` from a base64 encoded [`SessionKey`], using
+/// the default [`SessionConfig`].
+impl TryFrom<&str> for InboundGroupSession {
+    type Error = SessionKeyDecodeError;
+
+    fn try_from(session_key: &str) -> Result<Self, Self::Error> {
+        let session_key = SessionKey::from_base64(session_key)?;
+
+        Ok(Self::new(&session_key, SessionConfig::default()))
+    }
+}
+
+/// Create an `InboundGroupSession` from a base64 encoded [`SessionKey`], using
+/// the default [`SessionConfig`].
+///
+/// The string is zeroized after it has been decoded.
+impl TryFrom<String> for InboundGroupSession {
+    type Error = SessionKeyDecodeError;
+
+    fn try_from(mut session_key: String) -> Result<Self, Self::Error> {
+        let ret = Self::try_from(session_key.as_str());
+
+        session_key.zeroize();
+
+        ret
     }
 }
 
 #[cfg(test)]
 mod test {
-    use super::InboundGroupSession;
-    use crate::megolm::{GroupSession, SessionConfig, SessionOrdering};
+    use assert_matches::assert_matches;
+
+    #[cfg(feature = "serde")]
+    use super::InboundGroupSessionPickle;
+    use super::{decrypt_any, DecryptionError, InboundGroupSession, VerificationResult};
+    #[cfg(feature = "serde")]
+    use crate::fixture;
+    use crate::{
+        megolm::{
+            GroupSession, MegolmMessage, SessionConfig, SessionKey, SessionKeyDecodeError,
+            SessionOrdering,
+        },
+        utilities::base64_encode,
+        Ed25519Keypair, Ed25519Signature,
+    };
+
+    fn empty_signature() -> Ed25519Signature {
+        Ed25519Signature::from_slice(&[0; Ed25519Signature::LENGTH])
+            .expect("Can't create an empty signature")
+    }
+
+    /// Encode and sign a, potentially malformed, Megolm message.
+    fn forge_message(
+        signing_key: &Ed25519Keypair,
+        ciphertext: &[u8],
+        message_index: u32,
+        mac: &[u8],
+    ) -> MegolmMessage {
+        let unsigned = MegolmMessage::encode(ciphertext, message_index, mac, &empty_signature());
+        let signature = signing_key.sign(&unsigned[..unsigned.len() - Ed25519Signature::LENGTH]);
+
+        MegolmMessage::from_bytes(&MegolmMessage::encode(
+            ciphertext,
+            message_index,
+            mac,
+            &signature,
+        ))
+        .expect("A forged message should be decodable")
+    }
+
+    /// Create an inbound session whose signing key we control, so we can sign
+    /// forged messages.
+    fn forgeable_session() -> (GroupSession, InboundGroupSession, Ed25519Keypair) {
+        let outbound = GroupSession::new(Default::default());
+        let mut session = InboundGroupSession::from(&outbound);
+        let signing_key = Ed25519Keypair::new();
+        session.signing_key = signing_key.public_key();
+
+        (outbound, session, signing_key)
+    }
 
     #[test]
     fn advance_inbound_session() {
@@ -492,6 +1175,993 @@ mod test {
         assert_eq!(session.latest_ratchet.index(), 20);
     }
 
+    #[test]
+    fn latest_ratchet_index() {
+        let mut outbound = GroupSession::new(Default::default());
+        let mut session = InboundGroupSession::from(&outbound);
+
+        assert_eq!(session.latest_ratchet_index(), 0);
+
+        for _ in 0..5 {
+            outbound.encrypt("Skipped").expect("The session should be able to encrypt");
+        }
+
+        let message = outbound
+            .encrypt("It's a secret to everybody")
+            .expect("The session should be able to encrypt");
+        session.decrypt(&message).expect("We should be able to decrypt the message");
+
+        assert_eq!(session.first_known_index(), 0);
+        assert_eq!(session.latest_ratchet_index(), 5);
+    }
+
+    /// Create a session whose initial ratchet is at index 10 and whose latest
+    /// ratchet is at index 20.
+    fn session_with_advanced_latest_ratchet() -> InboundGroupSession {
+        let mut session = InboundGroupSession::from(&GroupSession::new(Default::default()));
+        session.advance_to(10);
+        session.latest_ratchet.advance_to(20);
+
+        session
+    }
+
+    /// The ratchet we expect at the given index, derived from the initial
+    /// ratchet of the session.
+    fn expected_ratchet(session: &InboundGroupSession, index: u32) -> [u8; 128] {
+        *session.initial_ratchet.advanced_to(index).as_bytes()
+    }
+
+    #[test]
+    fn find_ratchet_before_initial() {
+        let mut session = session_with_advanced_latest_ratchet();
+
+        assert!(session.find_ratchet(0).is_none());
+        assert!(session.find_ratchet(9).is_none());
+        assert_eq!(session.latest_ratchet_index(), 20);
+    }
+
+    #[test]
+    fn decrypting_messages_before_the_initial_index_fails() {
+        let mut outbound = GroupSession::new(Default::default());
+        let mut messages: Vec<_> = (0..5)
+            .map(|_| outbound.encrypt("It's a secret to everybody"))
+            .collect::<Result<_, _>>()
+            .expect("The session should be able to encrypt");
+
+        let mut session = InboundGroupSession::new(&outbound.session_key(), Default::default());
+        assert_eq!(session.first_known_index(), 5);
+
+        messages.push(
+            outbound.encrypt("It's a secret to everybody").expect("The session should encrypt"),
+        );
+
+        assert_matches!(
+            session.decrypt(&messages[2]),
+            Err(DecryptionError::UnknownMessageIndex(5, 2))
+        );
+        assert_matches!(
+            session.decrypt_bytes(&messages[2].to_bytes()),
+            Err(DecryptionError::UnknownMessageIndex(5, 2))
+        );
+
+        let decrypted =
+            session.decrypt(&messages[5]).expect("We should be able to decrypt the message");
+        assert_eq!(decrypted.message_index, 5);
+    }
+
+    #[test]
+    fn find_ratchet_equal_to_initial() {
+        let mut session = session_with_advanced_latest_ratchet();
+        let expected = expected_ratchet(&session, 10);
+
+        let ratchet = session.find_ratchet(10).expect("We should find the initial ratchet");
+
+        assert_eq!(ratchet.index(), 10);
+        assert_eq!(ratchet.as_bytes(), &expected);
+        assert_eq!(session.latest_ratchet_index(), 20);
+    }
+
+    #[test]
+    fn find_ratchet_equal_to_latest() {
+        let mut session = session_with_advanced_latest_ratchet();
+        let expected = expected_ratchet(&session, 20);
+
+        let ratchet = session.find_ratchet(20).expect("We should find the latest ratchet");
+
+        assert_eq!(ratchet.index(), 20);
+        assert_eq!(ratchet.as_bytes(), &expected);
+    }
+
+    #[test]
+    fn find_ratchet_forward_of_latest() {
+        let mut session = session_with_advanced_latest_ratchet();
+        let expected = expected_ratchet(&session, 30);
+
+        let ratchet = session.find_ratchet(30).expect("We should be able to advance the ratchet");
+
+        assert_eq!(ratchet.index(), 30);
+        assert_eq!(ratchet.as_bytes(), &expected);
+        assert_eq!(session.first_known_index(), 10);
+        assert_eq!(session.latest_ratchet_index(), 30);
+    }
+
+    #[test]
+    fn find_ratchet_between_initial_and_latest() {
+        let mut session = session_with_advanced_latest_ratchet();
+        let expected = expected_ratchet(&session, 15);
+
+        let ratchet = session.find_ratchet(15).expect("We should be able to restart the ratchet");
+
+        assert_eq!(ratchet.index(), 15);
+        assert_eq!(ratchet.as_bytes(), &expected);
+        assert_eq!(session.first_known_index(), 10);
+        assert_eq!(session.latest_ratchet_index(), 15);
+    }
+
+    #[test]
+    fn checkpoints_are_used_for_out_of_order_ratchets() {
+        let session = InboundGroupSession::from(&GroupSession::new(Default::default()));
+        let mut session = session.with_checkpointing(true);
+
+        let ratchet = session.find_ratchet(100).expect("We should be able to advance the ratchet");
+        assert_eq!(ratchet.index(), 100);
+
+        let checkpoints = session.checkpoints.as_ref().expect("Checkpointing should be enabled");
+        assert_eq!(checkpoints.keys().copied().collect::<Vec<_>>(), [1, 2, 4, 8, 16, 32, 64]);
+
+        for (&index, checkpoint) in checkpoints {
+            assert_eq!(checkpoint.index(), index);
+            assert_eq!(checkpoint.as_bytes(), &expected_ratchet(&session, index));
+        }
+
+        for index in [70, 3, 64, 99, 1, 33] {
+            let expected = expected_ratchet(&session, index);
+            let ratchet = session.find_ratchet(index).expect("We should find the ratchet");
+
+            assert_eq!(ratchet.index(), index);
+            assert_eq!(ratchet.as_bytes(), &expected);
+        }
+
+        session.advance_to(10);
+        let checkpoints = session.checkpoints.as_ref().expect("Checkpointing should be enabled");
+        assert_eq!(checkpoints.keys().copied().collect::<Vec<_>>(), [16, 32, 64]);
+    }
+
+    #[test]
+    fn checkpointing_is_disabled_by_default() {
+        let mut session = InboundGroupSession::from(&GroupSession::new(Default::default()));
+        session.find_ratchet(100);
+        assert!(session.checkpoints.is_none());
+
+        let mut session = session.with_checkpointing(true).with_checkpointing(false);
+        session.find_ratchet(200);
+        assert!(session.checkpoints.is_none());
+    }
+
+    #[test]
+    #[cfg(not(feature = "no-verify"))]
+    fn decrypting_a_message_of_another_session() {
+        let mut session = InboundGroupSession::from(&GroupSession::new(Default::default()));
+        let message = GroupSession::new(Default::default())
+            .encrypt("It's a secret to everybody")
+            .expect("The session should be able to encrypt");
+
+        let error = session.decrypt(&message).expect_err("The message belongs to another session");
+
+        assert_matches!(
+            error,
+            DecryptionError::Signature(key, _) if *key == session.signing_key
+        );
+    }
+
+    #[test]
+    fn decrypting_raw_bytes() {
+        let mut outbound = GroupSession::new(Default::default());
+        let mut session = InboundGroupSession::from(&outbound);
+
+        let message = outbound.encrypt("It's a secret to everybody").expect("Can encrypt");
+        let decrypted = session.decrypt_bytes(&message.to_bytes()).expect("Can decrypt raw bytes");
+
+        assert_eq!(decrypted.plaintext, b"It's a secret to everybody");
+        assert_eq!(decrypted.message_index, 0);
+
+        let error =
+            session.decrypt_bytes(&[3, 0, 0]).expect_err("Truncated bytes can't be decoded");
+        assert_matches!(error, DecryptionError::Decode(_));
+    }
+
+    #[test]
+    fn more_capable_sessions_have_a_lower_first_known_index() {
+        let outbound = GroupSession::new(Default::default());
+        let session = InboundGroupSession::from(&outbound);
+        let mut advanced = session.clone();
+        advanced.advance_to(10);
+
+        assert!(session.more_capable_than(&advanced));
+        assert!(!advanced.more_capable_than(&session));
+        assert!(!session.more_capable_than(&session.clone()));
+
+        let unrelated = InboundGroupSession::from(&GroupSession::new(Default::default()));
+
+        assert!(!unrelated.more_capable_than(&advanced));
+        assert!(!advanced.more_capable_than(&unrelated));
+    }
+
+    #[test]
+    fn decrypting_into_a_reused_buffer() {
+        let mut outbound = GroupSession::new(Default::default());
+        let mut session = InboundGroupSession::from(&outbound);
+
+        let mut out = Vec::with_capacity(64);
+        let buffer = out.as_ptr();
+
+        for index in 0..3 {
+            let message = outbound.encrypt("It's a secret to everybody").expect("Can encrypt");
+            let message_index =
+                session.decrypt_into(&message.to_base64(), &mut out).expect("Can decrypt");
+
+            assert_eq!(message_index, index);
+            assert_eq!(out, b"It's a secret to everybody");
+            assert_eq!(out.as_ptr(), buffer, "The buffer should be reused");
+        }
+
+        let message =
+            GroupSession::new(Default::default()).encrypt("Not for us").expect("Can encrypt");
+        session
+            .decrypt_into(&message.to_base64(), &mut out)
+            .expect_err("A message of another session can't be decrypted");
+        assert!(out.is_empty());
+    }
+
+    #[test]
+    fn decrypting_the_same_index_twice() {
+        let mut outbound = GroupSession::new(Default::default());
+        let mut session = InboundGroupSession::from(&outbound);
+
+        let message = outbound
+            .encrypt("It's a secret to everybody")
+            .expect("The session should be able to encrypt");
+
+        session.decrypt(&message).expect("We should be able to decrypt the message");
+
+        let decrypted =
+            session.decrypt(&message).expect("We should be able to decrypt the message again");
+        assert_eq!(decrypted.plaintext, b"It's a secret to everybody");
+        assert_eq!(decrypted.message_index, 0);
+
+        let message =
+            outbound.encrypt("Another secret").expect("The session should be able to encrypt");
+        session.decrypt(&message).expect("We should be able to decrypt the second message");
+
+        session.advance_to(2);
+        assert_matches!(session.decrypt(&message), Err(DecryptionError::UnknownMessageIndex(2, 1)));
+    }
+
+    #[test]
+    fn encoding_roundtrips() {
+        let mut outbound = GroupSession::new(Default::default());
+        let message = outbound
+            .encrypt("It's a secret to everybody")
+            .expect("The session should be able to encrypt");
+
+        let encoded = MegolmMessage::encode(
+            message.ciphertext(),
+            message.message_index(),
+            message.mac(),
+            message.signature(),
+        );
+
+        assert_eq!(encoded, message.to_bytes());
+
+        let mut outbound = GroupSession::new(SessionConfig::version_1());
+        let message = outbound
+            .encrypt("It's a secret to everybody")
+            .expect("The session should be able to encrypt");
+
+        let encoded = MegolmMessage::encode(
+            message.ciphertext(),
+            message.message_index(),
+            message.mac(),
+            message.signature(),
+        );
+
+        assert_eq!(encoded, message.to_bytes());
+    }
+
+    #[test]
+    fn mac_length_must_match_the_session_config() {
+        let mut truncated = GroupSession::new(SessionConfig::version_1());
+        let mut full = GroupSession::new(SessionConfig::version_2());
+
+        let mut session =
+            InboundGroupSession::new(&truncated.session_key(), SessionConfig::version_2());
+        assert_matches!(
+            session.decrypt(
+                &truncated
+                    .encrypt("It's a secret to everybody")
+                    .expect("The session should be able to encrypt")
+            ),
+            Err(DecryptionError::InvalidMACLength(32, 8))
+        );
+
+        let mut session = InboundGroupSession::new(&full.session_key(), SessionConfig::version_1());
+        assert_matches!(
+            session.decrypt(
+                &full
+                    .encrypt("It's a secret to everybody")
+                    .expect("The session should be able to encrypt")
+            ),
+            Err(DecryptionError::InvalidMACLength(8, 32))
+        );
+    }
+
+    #[test]
+    fn forged_message_with_invalid_mac() {
+        let (mut outbound, mut session, signing_key) = forgeable_session();
+        let message = outbound
+            .encrypt("It's a secret to everybody")
+            .expect("The session should be able to encrypt");
+
+        let forged = forge_message(&signing_key, message.ciphertext(), 0, &[0u8; 32]);
+        assert_matches!(session.decrypt(&forged), Err(DecryptionError::InvalidMAC(_)));
+
+        let forged = forge_message(&signing_key, message.ciphertext(), 0, &[0u8; 8]);
+        assert_matches!(session.decrypt(&forged), Err(DecryptionError::InvalidMACLength(32, 8)));
+    }
+
+    #[test]
+    #[cfg(feature = "gcm")]
+    fn forged_gcm_message_with_invalid_tag() {
+        let mut outbound = GroupSession::new(SessionConfig::gcm());
+        let mut session = InboundGroupSession::from(&outbound);
+        let signing_key = Ed25519Keypair::new();
+        session.signing_key = signing_key.public_key();
+
+        let message = outbound
+            .encrypt("It's a secret to everybody")
+            .expect("The session should be able to encrypt");
+
+        let mut ciphertext = message.ciphertext().to_vec();
+        ciphertext[0] ^= 1;
+        let forged = forge_message(&signing_key, &ciphertext, 0, message.mac());
+        assert_matches!(session.decrypt(&forged), Err(DecryptionError::InvalidMAC(_)));
+
+        let forged = forge_message(&signing_key, message.ciphertext(), 1, message.mac());
+        assert_matches!(session.decrypt(&forged), Err(DecryptionError::InvalidMAC(_)));
+
+        let forged = forge_message(&signing_key, message.ciphertext(), 0, &[0u8; 32]);
+        assert_matches!(session.decrypt(&forged), Err(DecryptionError::InvalidMACLength(16, 32)));
+
+        let forged = forge_message(&signing_key, message.ciphertext(), 0, message.mac());
+        let decrypted = session.decrypt(&forged).expect("The untouched message should decrypt");
+        assert_eq!(decrypted.plaintext, b"It's a secret to everybody");
+    }
+
+    #[test]
+    #[cfg(not(feature = "no-verify"))]
+    fn message_with_invalid_signature() {
+        let (mut outbound, mut session, _) = forgeable_session();
+        let message = outbound
+            .encrypt("It's a secret to everybody")
+            .expect("The session should be able to encrypt");
+
+        let forged = forge_message(&Ed25519Keypair::new(), message.ciphertext(), 0, message.mac());
+        let error = session.decrypt(&forged).expect_err("A forged signature should be rejected");
+
+        assert_matches!(&error, DecryptionError::Signature(key, _) if **key == session.signing_key);
+        assert!(error.to_string().contains(&session.session_id()));
+    }
+
+    #[test]
+    #[cfg(feature = "no-verify")]
+    fn message_with_invalid_signature_is_accepted_without_verification() {
+        let (mut outbound, mut session, signing_key) = forgeable_session();
+        let message = outbound
+            .encrypt("It's a secret to everybody")
+            .expect("The session should be able to encrypt");
+
+        let forged = forge_message(&Ed25519Keypair::new(), message.ciphertext(), 0, message.mac());
+        let decrypted = session.decrypt(&forged).expect("The signature should not be checked");
+
+        assert_eq!(decrypted.plaintext, b"It's a secret to everybody");
+        assert_eq!(session.signing_key, signing_key.public_key());
+    }
+
+    #[test]
+    fn forged_message_with_truncated_ciphertext() {
+        let (mut outbound, mut session, signing_key) = forgeable_session();
+        let message = outbound
+            .encrypt("It's a secret to everybody")
+            .expect("The session should be able to encrypt");
+
+        let ciphertext = &message.ciphertext()[..message.ciphertext().len() - 1];
+        let cipher = session.cipher_at(0).expect("We should have a cipher for index 0");
+        let unauthenticated = MegolmMessage::encode(ciphertext, 0, &[], &empty_signature());
+        let mac = cipher.mac(&unauthenticated[..unauthenticated.len() - Ed25519Signature::LENGTH]);
+
+        let forged = forge_message(&signing_key, ciphertext, 0, mac.as_bytes());
+        assert_matches!(session.decrypt(&forged), Err(DecryptionError::InvalidPadding(_)));
+    }
+
+    #[test]
+    fn forged_message_with_wrong_index() {
+        let (mut outbound, mut session, signing_key) = forgeable_session();
+        let message = outbound
+            .encrypt("It's a secret to everybody")
+            .expect("The session should be able to encrypt");
+
+        let forged = forge_message(&signing_key, message.ciphertext(), 1, message.mac());
+        assert_matches!(session.decrypt(&forged), Err(DecryptionError::InvalidMAC(_)));
+
+        session.advance_to(5);
+
+        let forged = forge_message(&signing_key, message.ciphertext(), 0, message.mac());
+        assert_matches!(session.decrypt(&forged), Err(DecryptionError::UnknownMessageIndex(5, 0)));
+    }
+
+    #[test]
+    fn batch_import() {
+        let outbound = GroupSession::new(Default::default());
+        let session_key = outbound.session_key().to_base64();
+        let exported_key =
+            InboundGroupSession::from(&outbound).export_at_first_known_index().to_base64();
+
+        let results = InboundGroupSession::import_batch(
+            [session_key.as_str(), exported_key.as_str(), "not a session key"],
+            Default::default(),
+        );
+
+        assert_eq!(results.len(), 3);
+
+        let verified = results[0].as_ref().expect("The session key should be imported");
+        assert!(verified.verified);
+        assert!(verified.session.is_verified());
+        assert_eq!(verified.session.session_id(), outbound.session_id());
+
+        let unverified = results[1].as_ref().expect("The exported session key should be imported");
+        assert!(!unverified.verified);
+        assert!(!unverified.session.is_verified());
+        assert_eq!(unverified.session.session_id(), outbound.session_id());
+
+        assert!(results[2].is_err());
+    }
+
+    #[test]
+    fn session_from_base64_string() -> Result<(), SessionKeyDecodeError> {
+        let outbound = GroupSession::new(Default::default());
+        let session_key = outbound.session_key().to_base64();
+
+        let session = InboundGroupSession::try_from(session_key.as_str())?;
+        assert_eq!(session.session_id(), outbound.session_id());
+        assert!(session.is_verified());
+
+        let session: InboundGroupSession = session_key.try_into()?;
+        assert_eq!(session.session_id(), outbound.session_id());
+
+        assert!(matches!(
+            InboundGroupSession::try_from("not a session key"),
+            Err(SessionKeyDecodeError::Base64(_))
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn batch_import_rejects_unknown_versions() {
+        let outbound = GroupSession::new(Default::default());
+        let mut bytes = outbound.session_key().to_bytes();
+        bytes[0] = 3;
+        let unknown_version = base64_encode(bytes);
+
+        let results =
+            InboundGroupSession::import_batch([unknown_version.as_str()], Default::default());
+
+        assert!(matches!(
+            results.as_slice(),
+            [Err(SessionKeyDecodeError::Version(SessionKey::VERSION, 3))]
+        ));
+    }
+
+    #[test]
+    #[cfg(feature = "libolm-compat")]
+    fn libolm_pickle_roundtrip() -> Result<(), crate::LibolmPickleError> {
+        let mut outbound = GroupSession::new(SessionConfig::version_1());
+        let mut session = InboundGroupSession::import(
+            &InboundGroupSession::from(&outbound).export_at_first_known_index(),
+            SessionConfig::version_1(),
+        );
+
+        let message = outbound.encrypt("Hello").expect("The session should be able to encrypt");
+        outbound.encrypt("World").expect("The session should be able to encrypt");
+        session.decrypt(&message).expect("The message should decrypt");
+
+        let pickle_key = b"It's a secret to everybody";
+        let pickle = session.to_libolm_pickle(pickle_key)?;
+        let unpickled = InboundGroupSession::from_libolm_pickle(&pickle, pickle_key)?;
+
+        assert_eq!(unpickled.session_id(), session.session_id());
+        assert_eq!(unpickled.first_known_index(), session.first_known_index());
+        assert_eq!(unpickled.latest_ratchet.index(), session.latest_ratchet.index());
+        assert_eq!(unpickled.is_verified(), session.is_verified());
+        assert!(InboundGroupSession::from_libolm_pickle(&pickle, b"wrong key").is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    #[cfg(feature = "libolm-compat")]
+    fn libolm_pickling_rejects_unsupported_configs() {
+        let session = InboundGroupSession::from(&GroupSession::new(SessionConfig::version_2()));
+
+        assert_matches!(
+            session.to_libolm_pickle(b"It's a secret to everybody"),
+            Err(crate::LibolmPickleError::UnsupportedSessionConfig(2))
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "serde")]
+    fn unpickling_stored_fixture() {
+        let pickle = InboundGroupSessionPickle::from_encrypted(
+            &fixture("inbound_group_session.pickle"),
+            &[0; 32],
+        )
+        .expect("We should be able to unpickle the stored inbound group session");
+        let session = InboundGroupSession::from_pickle(pickle);
+
+        assert_eq!(session.session_id(), "8s9NRcNZhYoMlgmKNYk5yb49/w9b+xP4vI4qqfXJ/08");
+        assert_eq!(session.first_known_index(), 2);
+    }
+
+    #[test]
+    fn decrypted_message_contains_the_verified_mac() {
+        for config in [SessionConfig::version_1(), SessionConfig::version_2()] {
+            let mut outbound = GroupSession::new(config);
+            let mut session = InboundGroupSession::from(&outbound);
+
+            let message = outbound
+                .encrypt("It's a secret to everybody")
+                .expect("The session should be able to encrypt");
+            let decrypted = session.decrypt(&message).expect("The message should decrypt");
+
+            assert_eq!(decrypted.mac.as_slice(), message.mac());
+            assert_eq!(decrypted.mac.len(), config.mac_length());
+        }
+    }
+
+    #[test]
+    fn skipped_messages_are_reported() {
+        let mut outbound = GroupSession::new(Default::default());
+        let mut session = InboundGroupSession::from(&outbound);
+
+        let first = outbound
+            .encrypt("It's dangerous to go alone")
+            .expect("The session should be able to encrypt");
+        let second = outbound.encrypt("Take this").expect("The session should be able to encrypt");
+
+        for _ in 0..4 {
+            outbound
+                .encrypt("It's a secret to everybody")
+                .expect("The session should be able to encrypt");
+        }
+
+        let seventh =
+            outbound.encrypt("Grumble, grumble").expect("The session should be able to encrypt");
+
+        let decrypted = session.decrypt(&first).expect("We should be able to decrypt");
+        assert_eq!(decrypted.skipped, 0);
+
+        let decrypted = session.decrypt(&second).expect("We should be able to decrypt");
+        assert_eq!(decrypted.skipped, 0);
+
+        let decrypted = session.decrypt(&seventh).expect("We should be able to decrypt");
+        assert_eq!(decrypted.message_index, 6);
+        assert_eq!(decrypted.skipped, 4);
+
+        let decrypted = session.decrypt(&seventh).expect("We should be able to decrypt");
+        assert_eq!(decrypted.skipped, 0);
+
+        let decrypted = session.decrypt(&second).expect("We should be able to decrypt");
+        assert_eq!(decrypted.skipped, 0);
+    }
+
+    #[test]
+    fn skipped_messages_of_the_first_decryption() {
+        let mut outbound = GroupSession::new(Default::default());
+        let mut session = InboundGroupSession::from(&outbound);
+
+        for _ in 0..3 {
+            outbound
+                .encrypt("It's a secret to everybody")
+                .expect("The session should be able to encrypt");
+        }
+
+        let mut imported = InboundGroupSession::import(
+            &session.export_at(3).expect("The session should be exportable at index 3"),
+            Default::default(),
+        );
+
+        let fourth = outbound.encrypt("Take this").expect("The session should be able to encrypt");
+        let fifth =
+            outbound.encrypt("Grumble, grumble").expect("The session should be able to encrypt");
+
+        let decrypted = session.decrypt(&fourth).expect("We should be able to decrypt");
+        assert_eq!(decrypted.message_index, 3);
+        assert_eq!(decrypted.skipped, 3);
+
+        let decrypted = imported.decrypt(&fourth).expect("We should be able to decrypt");
+        assert_eq!(decrypted.skipped, 0);
+
+        let decrypted = imported.decrypt(&fifth).expect("We should be able to decrypt");
+        assert_eq!(decrypted.skipped, 0);
+
+        let mut imported = InboundGroupSession::import(
+            &session.export_at(3).expect("The session should be exportable at index 3"),
+            Default::default(),
+        );
+
+        let decrypted = imported.decrypt(&fifth).expect("We should be able to decrypt");
+        assert_eq!(decrypted.message_index, 4);
+        assert_eq!(decrypted.skipped, 1);
+    }
+
+    #[test]
+    fn inspecting_messages() {
+        let mut outbound = GroupSession::new(Default::default());
+        let mut session = InboundGroupSession::from(&outbound);
+
+        outbound
+            .encrypt("It's dangerous to go alone")
+            .expect("The session should be able to encrypt");
+        outbound.encrypt("Take this").expect("The session should be able to encrypt");
+        let message = outbound
+            .encrypt("It's a secret to everybody")
+            .expect("The session should be able to encrypt")
+            .to_base64();
+
+        session.advance_to(5);
+
+        let metadata = session.inspect(&message).expect("The message should be inspectable");
+
+        assert_eq!(metadata.message_index, 2);
+        assert_eq!(metadata.signing_key, session.signing_key);
+        assert!(metadata.signature_valid);
+
+        let unrelated = GroupSession::new(Default::default())
+            .encrypt("Take this")
+            .expect("The session should be able to encrypt")
+            .to_base64();
+        let metadata = session.inspect(&unrelated).expect("The message should be inspectable");
+
+        assert_eq!(metadata.message_index, 0);
+        assert!(!metadata.signature_valid);
+
+        assert_matches!(session.inspect("not a message"), Err(DecryptionError::Decode(_)));
+    }
+
+    #[test]
+    fn decrypting_with_any_session() {
+        let mut first = GroupSession::new(Default::default());
+        let mut second = GroupSession::new(Default::default());
+        let mut unrelated = GroupSession::new(Default::default());
+
+        let mut sessions = [InboundGroupSession::from(&first), InboundGroupSession::from(&second)];
+
+        let message = second
+            .encrypt("It's dangerous to go alone")
+            .expect("The session should be able to encrypt")
+            .to_base64();
+        let (index, decrypted) =
+            decrypt_any(&mut sessions, &message).expect("The second session should be picked");
+
+        assert_eq!(index, 1);
+        assert_eq!(decrypted.plaintext, b"It's dangerous to go alone");
+
+        let message =
+            first.encrypt("Take this").expect("The session should be able to encrypt").to_base64();
+        let (index, decrypted) =
+            decrypt_any(&mut sessions, &message).expect("The first session should be picked");
+
+        assert_eq!(index, 0);
+        assert_eq!(decrypted.plaintext, b"Take this");
+
+        let message = unrelated
+            .encrypt("Take this")
+            .expect("The session should be able to encrypt")
+            .to_base64();
+        assert_matches!(decrypt_any(&mut sessions, &message), Err(DecryptionError::UnknownSession));
+        assert_matches!(
+            decrypt_any(&mut sessions, "not a message"),
+            Err(DecryptionError::Decode(_))
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "rayon")]
+    fn decrypting_a_batch() {
+        use super::decrypt_batch;
+
+        let mut first = GroupSession::new(Default::default());
+        let mut second = GroupSession::new(Default::default());
+        let first_inbound = InboundGroupSession::from(&first);
+        let second_inbound = InboundGroupSession::from(&second);
+
+        let first_messages: Vec<_> = ["Hello", "there"]
+            .iter()
+            .map(|m| first.encrypt(m).expect("The session should be able to encrypt").to_base64())
+            .collect();
+        let second_message = second
+            .encrypt("General Kenobi")
+            .expect("The session should be able to encrypt")
+            .to_base64();
+
+        let mut batch = [
+            (first_inbound, first_messages.iter().map(String::as_str).collect()),
+            (second_inbound, vec![second_message.as_str(), first_messages[0].as_str()]),
+        ];
+
+        let results = decrypt_batch(&mut batch);
+
+        assert_eq!(results.len(), 2);
+
+        let first_plaintexts: Vec<_> = results[0]
+            .iter()
+            .map(|r| r.as_ref().expect("The message should decrypt").plaintext.as_slice())
+            .collect();
+        assert_eq!(first_plaintexts, [b"Hello".as_slice(), b"there"]);
+
+        assert_eq!(
+            results[1][0].as_ref().expect("The message should decrypt").plaintext,
+            b"General Kenobi"
+        );
+        assert!(results[1][1].is_err());
+        assert_eq!(batch[0].0.latest_ratchet.index(), 1);
+    }
+
+    #[test]
+    #[cfg(feature = "dangerous-export")]
+    fn dangerous_ratchet_export() {
+        let mut outbound = GroupSession::new(Default::default());
+        let mut session = InboundGroupSession::from(&outbound);
+        outbound
+            .encrypt("It's dangerous to go alone")
+            .expect("The session should be able to encrypt");
+        let message = outbound
+            .encrypt("It's a secret to everybody")
+            .expect("The session should be able to encrypt");
+
+        assert_eq!(
+            session.__dangerous_ratchet_bytes().as_slice(),
+            session.initial_ratchet.as_bytes()
+        );
+
+        session.decrypt(&message).expect("The message should decrypt");
+
+        assert_eq!(
+            session.__dangerous_ratchet_bytes().as_slice(),
+            session.latest_ratchet.as_bytes()
+        );
+        assert_ne!(
+            session.__dangerous_ratchet_bytes().as_slice(),
+            session.initial_ratchet.as_bytes()
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "dangerous-export")]
+    fn message_keys_match_the_ciphers() {
+        use crate::cipher::Cipher;
+
+        let mut outbound = GroupSession::new(Default::default());
+        for _ in 0..3 {
+            outbound.encrypt("Skipped").expect("The session should be able to encrypt");
+        }
+        let session = InboundGroupSession::from(&outbound);
+
+        let keys: Vec<_> = session.message_keys(0, 8).collect();
+        assert_eq!(
+            keys.iter().map(|(index, _)| *index).collect::<Vec<_>>(),
+            (3..8).collect::<Vec<_>>()
+        );
+
+        for (index, keys) in &keys {
+            let ratchet = session.initial_ratchet.advanced_to(*index);
+            let expected = Cipher::new_megolm(ratchet.as_bytes()).message_keys();
+
+            assert_eq!(keys.aes_key, expected.aes_key);
+            assert_eq!(keys.aes_iv, expected.aes_iv);
+            assert_eq!(keys.mac_key, expected.mac_key);
+        }
+
+        assert_eq!(session.message_keys(5, 5).count(), 0);
+        assert_eq!(session.message_keys(9, 4).count(), 0);
+        assert_eq!(session.latest_ratchet.index(), 3);
+    }
+
+    /// Reusing an IV, together with the AES key, for two different messages
+    /// would be catastrophic. Every message index needs to produce its own IV,
+    /// and the same index needs to produce the same IV so the receiver can
+    /// decrypt the message.
+    #[test]
+    #[cfg(feature = "dangerous-export")]
+    fn message_ivs_are_unique_and_deterministic() {
+        use std::collections::HashSet;
+
+        let outbound = GroupSession::new(Default::default());
+        let session = InboundGroupSession::from(&outbound);
+        let other_session = InboundGroupSession::new(&outbound.session_key(), Default::default());
+
+        // Cross the 256 boundary, where the second part of the ratchet is
+        // advanced for the first time.
+        let ivs: Vec<_> = session.message_keys(0, 600).map(|(_, keys)| keys.aes_iv).collect();
+        let unique: HashSet<_> = ivs.iter().collect();
+        assert_eq!(unique.len(), ivs.len());
+
+        let other_ivs: Vec<_> =
+            other_session.message_keys(0, 600).map(|(_, keys)| keys.aes_iv).collect();
+        assert_eq!(ivs, other_ivs);
+
+        let (_, keys) = session.message_keys(300, 301).next().expect("We should derive index 300");
+        assert_eq!(keys.aes_iv, ivs[300]);
+
+        let unrelated = InboundGroupSession::from(&GroupSession::new(Default::default()));
+        let (_, keys) = unrelated.message_keys(0, 1).next().expect("We should derive index 0");
+        assert_ne!(keys.aes_iv, ivs[0]);
+    }
+
+    #[test]
+    fn decrypted_messages_report_their_verification() {
+        let mut outbound = GroupSession::new(Default::default());
+        let mut verified = InboundGroupSession::new(&outbound.session_key(), Default::default());
+        let mut unverified = InboundGroupSession::import(
+            &verified.export_at_first_known_index(),
+            Default::default(),
+        );
+
+        let message = outbound
+            .encrypt("It's a secret to everybody")
+            .expect("The session should be able to encrypt");
+        let signature_verified = !cfg!(feature = "no-verify");
+
+        let decrypted = verified.decrypt(&message).expect("We should be able to decrypt");
+        assert_eq!(
+            decrypted.verification,
+            VerificationResult { signing_key_verified: true, signature_verified }
+        );
+
+        let decrypted = unverified.decrypt(&message).expect("We should be able to decrypt");
+        assert_eq!(
+            decrypted.verification,
+            VerificationResult { signing_key_verified: false, signature_verified }
+        );
+
+        let (_, decrypted) = decrypt_any(&mut [unverified], &message.to_base64())
+            .expect("We should be able to decrypt");
+        assert!(decrypted.verification.signature_verified);
+        assert!(!decrypted.verification.signing_key_verified);
+    }
+
+    #[test]
+    fn plaintext_size_limit() {
+        let mut outbound = GroupSession::new(Default::default());
+        let mut session = InboundGroupSession::from(&outbound);
+        session.set_max_plaintext_size(20);
+
+        let message = outbound.encrypt([0u8; 20]).expect("The session should be able to encrypt");
+        let decrypted = session.decrypt(&message).expect("The plaintext fits the limit");
+        assert_eq!(decrypted.plaintext.len(), 20);
+
+        // 24 bytes of plaintext are padded to 32 bytes of ciphertext, which
+        // could still hold a plaintext of only 16 bytes.
+        let message = outbound.encrypt([0u8; 24]).expect("The session should be able to encrypt");
+        assert_matches!(session.decrypt(&message), Err(DecryptionError::PlaintextTooLarge(20, 24)));
+
+        let message = outbound.encrypt([0u8; 100]).expect("The session should be able to encrypt");
+        assert_matches!(session.decrypt(&message), Err(DecryptionError::PlaintextTooLarge(20, 96)));
+        assert_eq!(session.latest_ratchet_index(), 1);
+    }
+
+    #[test]
+    #[cfg(feature = "serde")]
+    fn pickle_only_stores_the_initial_ratchet() {
+        let mut outbound = GroupSession::new(Default::default());
+        let mut session = InboundGroupSession::from(&outbound);
+
+        let messages: Vec<_> = (0..5)
+            .map(|_| {
+                outbound
+                    .encrypt("It's a secret to everybody")
+                    .expect("The session should be able to encrypt")
+            })
+            .collect();
+
+        session.decrypt(&messages[3]).expect("We should be able to decrypt");
+        assert_eq!(session.latest_ratchet_index(), 3);
+
+        let pickle = serde_json::to_value(session.pickle()).expect("The pickle should serialize");
+        assert!(pickle.get("latest_ratchet").is_none());
+
+        let mut unpickled = InboundGroupSession::from_pickle(
+            serde_json::from_value(pickle).expect("The pickle should deserialize"),
+        );
+        assert_eq!(unpickled.latest_ratchet_index(), unpickled.first_known_index());
+
+        for message in &messages {
+            let expected = session.decrypt(message).expect("We should be able to decrypt");
+            let decrypted = unpickled.decrypt(message).expect("We should be able to decrypt");
+
+            assert_eq!(decrypted.plaintext, expected.plaintext);
+            assert_eq!(decrypted.message_index, expected.message_index);
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "serde")]
+    fn pickle_stores_the_signing_key_as_bytes() {
+        let outbound = GroupSession::new(Default::default());
+        let session = InboundGroupSession::from(&outbound);
+
+        let pickle = serde_json::to_value(session.pickle()).expect("The pickle should serialize");
+        let expected = serde_json::to_value(session.signing_key.as_bytes())
+            .expect("The signing key bytes should serialize");
+        assert_eq!(pickle["signing_key"], expected);
+
+        let unpickled = InboundGroupSession::from_pickle(
+            serde_json::from_value(pickle).expect("The pickle should deserialize"),
+        );
+        assert_eq!(unpickled.signing_key, session.signing_key);
+    }
+
+    #[test]
+    fn session_id_is_the_signing_key() {
+        let outbound = GroupSession::new(Default::default());
+        let session = InboundGroupSession::from(&outbound);
+
+        assert_eq!(session.session_id(), outbound.session_id());
+        assert_eq!(session.session_id(), session.signing_key.to_base64());
+    }
+
+    #[test]
+    fn clones_are_independent() {
+        let mut outbound = GroupSession::new(Default::default());
+        let mut session = InboundGroupSession::from(&outbound);
+
+        let first = outbound.encrypt("First").expect("The session should be able to encrypt");
+        session.decrypt(&first).expect("We should be able to decrypt the first message");
+
+        let clone = session.clone();
+
+        for _ in 0..9 {
+            outbound.encrypt("Skipped").expect("The session should be able to encrypt");
+        }
+
+        let message = outbound.encrypt("Tenth").expect("The session should be able to encrypt");
+        session.decrypt(&message).expect("We should be able to decrypt the tenth message");
+
+        assert_eq!(session.latest_ratchet_index(), 10);
+        assert_eq!(clone.latest_ratchet_index(), 0);
+        assert_eq!(clone.first_known_index(), session.first_known_index());
+    }
+
+    #[test]
+    fn deferred_verification() {
+        let outbound = GroupSession::new(Default::default());
+        let verified = InboundGroupSession::new(&outbound.session_key(), Default::default());
+        let mut session = InboundGroupSession::import(
+            &verified.export_at_first_known_index(),
+            Default::default(),
+        );
+        let other = InboundGroupSession::from(&GroupSession::new(Default::default()));
+
+        assert!(!session.is_verified());
+
+        assert!(!session.mark_verified_if_matches(&other.signing_key));
+        assert!(!session.is_verified());
+
+        assert!(session.mark_verified_if_matches(&verified.signing_key));
+        assert!(session.is_verified());
+    }
+
     #[test]
     fn connecting() {
         let outbound = GroupSession::new(Default::default());
@@ -578,8 +2248,8 @@ mod test {
         let mut group_session = GroupSession::new(Default::default());
 
         // Advance the ratchet a few times by calling `encrypt`.
-        group_session.encrypt("test1");
-        group_session.encrypt("test2");
+        group_session.encrypt("test1").expect("The session should be able to encrypt");
+        group_session.encrypt("test2").expect("The session should be able to encrypt");
 
         let session = InboundGroupSession::from(&group_session);
 
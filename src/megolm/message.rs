@@ -13,9 +13,15 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use prost::Message;
+use prost::{
+    encoding::{self, DecodeContext, WireType},
+    DecodeError as ProtoBufDecodeError, Message,
+};
+#[cfg(feature = "serde")]
 use serde::{Deserialize, Serialize};
 
+#[cfg(feature = "gcm")]
+use crate::cipher::{GCM_NONCE_LEN, GCM_TAG_LEN};
 use crate::{
     cipher::{Cipher, Mac, MessageMac},
     types::{Ed25519Keypair, Ed25519Signature},
@@ -25,9 +31,6 @@ use crate::{
 #[cfg(feature = "low-level-api")]
 use crate::{Ed25519PublicKey, SignatureError};
 
-const MAC_TRUNCATED_VERSION: u8 = 3;
-const VERSION: u8 = 4;
-
 /// An encrypted Megolm message.
 ///
 /// Contains metadata that is required to find the correct ratchet state of a
@@ -39,13 +42,64 @@ pub struct MegolmMessage {
     pub(super) version: u8,
     pub(super) ciphertext: Vec<u8>,
     pub(super) message_index: u32,
-    pub(super) mac: MessageMac,
+    pub(super) mac: MegolmMac,
     pub(super) signature: Ed25519Signature,
 }
 
+/// The authenticator of a [`MegolmMessage`].
+///
+/// The AES-CBC modes use a HMAC, the AES-GCM mode uses the authentication tag
+/// of the cipher instead. The tag isn't a valid authenticator for Olm
+/// messages, so it doesn't live in the shared [`MessageMac`] type.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(super) enum MegolmMac {
+    Hmac(MessageMac),
+    #[cfg(feature = "gcm")]
+    Gcm([u8; GCM_TAG_LEN]),
+}
+
+impl MegolmMac {
+    fn as_bytes(&self) -> &[u8] {
+        match self {
+            MegolmMac::Hmac(m) => m.as_bytes(),
+            #[cfg(feature = "gcm")]
+            MegolmMac::Gcm(t) => t.as_ref(),
+        }
+    }
+}
+
+impl From<MessageMac> for MegolmMac {
+    fn from(mac: MessageMac) -> Self {
+        Self::Hmac(mac)
+    }
+}
+
 impl MegolmMessage {
+    /// The version byte of a message using a full, untruncated, MAC. Such
+    /// messages are produced by [`SessionConfig::version_2()`] sessions and
+    /// aren't understood by libolm.
+    ///
+    /// [`SessionConfig::version_2()`]: crate::megolm::SessionConfig::version_2
+    pub const VERSION: u8 = 4;
+    /// The version byte of a message using a MAC truncated to eight bytes.
+    /// This is the format libolm produces, used by
+    /// [`SessionConfig::version_1()`] sessions.
+    ///
+    /// [`SessionConfig::version_1()`]: crate::megolm::SessionConfig::version_1
+    pub const MAC_TRUNCATED_VERSION: u8 = 3;
+    /// The version byte of a message encrypted using AES-256-GCM, the MAC of
+    /// such messages is the 16 byte authentication tag. These messages are
+    /// produced by [`SessionConfig::gcm()`] sessions and are specific to
+    /// vodozemac.
+    ///
+    /// [`SessionConfig::gcm()`]: crate::megolm::SessionConfig::gcm
+    #[cfg(feature = "gcm")]
+    pub const GCM_VERSION: u8 = 5;
+
     const MESSAGE_TRUNCATED_SUFFIX_LENGTH: usize = Mac::TRUNCATED_LEN + Ed25519Signature::LENGTH;
     const MESSAGE_SUFFIX_LENGTH: usize = Mac::LENGTH + Ed25519Signature::LENGTH;
+    #[cfg(feature = "gcm")]
+    const MESSAGE_GCM_SUFFIX_LENGTH: usize = GCM_TAG_LEN + Ed25519Signature::LENGTH;
 
     /// The actual ciphertext of the message.
     pub fn ciphertext(&self) -> &[u8] {
@@ -95,6 +149,12 @@ impl MegolmMessage {
     /// :-----------:|:-----:|:------:|:---------------------------------------:
     /// Message-Index|  0x08 | Integer|The index of the ratchet, i
     /// Cipher-Text  |  0x12 | String |The cipher-text, Xi, of the message
+    ///
+    /// The message index is encoded as a Protocol Buffers varint. This differs
+    /// from the ratchet index of a [`SessionKey`], which is encoded as a
+    /// big-endian 32-bit integer.
+    ///
+    /// [`SessionKey`]: crate::megolm::SessionKey
     pub fn to_bytes(&self) -> Vec<u8> {
         let mut message = self.encode_message();
 
@@ -112,6 +172,20 @@ impl MegolmMessage {
         Self::try_from(message)
     }
 
+    /// Read the message index of a base64 encoded [`MegolmMessage`] without
+    /// decoding the rest of the message.
+    ///
+    /// No keys are needed for this, which allows code that doesn't hold any
+    /// session state to sort or shard messages by their index. Nothing about
+    /// the message is verified, the index should only be trusted once the
+    /// message has been decrypted.
+    pub fn peek_index(ciphertext_b64: &str) -> Result<u32, DecodeError> {
+        let message = base64_decode(ciphertext_b64)?;
+        let payload = Self::payload(&message)?;
+
+        Ok(ProtobufMegolmMessage::peek_index(payload)?)
+    }
+
     /// Encode the [`MegolmMessage`] as a string.
     ///
     /// This method first calls [`MegolmMessage::to_bytes()`] and then encodes
@@ -120,6 +194,37 @@ impl MegolmMessage {
         base64_encode(self.to_bytes())
     }
 
+    /// Encode the given parts of a Megolm message into the wire format
+    /// described in the [`MegolmMessage::to_bytes()`] method.
+    ///
+    /// No validation of the parts is performed, this allows deliberately
+    /// malformed messages to be constructed. The message version is picked
+    /// based on the length of the `mac`, an 8 byte MAC results in a message
+    /// with a truncated MAC. With the `gcm` feature, a 16 byte MAC results in
+    /// an AES-GCM message.
+    #[cfg(any(test, feature = "testing"))]
+    pub fn encode(
+        ciphertext: &[u8],
+        message_index: u32,
+        mac: &[u8],
+        signature: &Ed25519Signature,
+    ) -> Vec<u8> {
+        let version = match mac.len() {
+            Mac::TRUNCATED_LEN => Self::MAC_TRUNCATED_VERSION,
+            #[cfg(feature = "gcm")]
+            GCM_TAG_LEN => Self::GCM_VERSION,
+            _ => Self::VERSION,
+        };
+
+        let message = ProtobufMegolmMessage { message_index, ciphertext: ciphertext.to_vec() };
+
+        let mut bytes = message.encode_manual(version);
+        bytes.extend(mac);
+        bytes.extend(signature.to_bytes());
+
+        bytes
+    }
+
     /// Set the signature of the message, verifying that the signature matches
     /// the signing key.
     #[cfg(feature = "low-level-api")]
@@ -144,13 +249,6 @@ impl MegolmMessage {
         message.encode_manual(self.version)
     }
 
-    fn set_mac(&mut self, mac: Mac) {
-        match self.mac {
-            MessageMac::Truncated(_) => self.mac = mac.truncate().into(),
-            MessageMac::Full(_) => self.mac = mac.into(),
-        }
-    }
-
     /// Create a new [`MegolmMessage`] with the given plaintext and keys.
     #[cfg(feature = "low-level-api")]
     pub fn encrypt(
@@ -172,16 +270,14 @@ impl MegolmMessage {
     ) -> Self {
         let ciphertext = cipher.encrypt(plaintext);
 
-        let message = Self {
-            version: VERSION,
-            ciphertext,
+        Self::encrypt_helper(
+            cipher,
+            signing_key,
+            Self::VERSION,
             message_index,
-            mac: Mac([0u8; Mac::LENGTH]).into(),
-            signature: Ed25519Signature::from_slice(&[0; Ed25519Signature::LENGTH])
-                .expect("Can't create an empty signature"),
-        };
-
-        Self::encrypt_helper(cipher, signing_key, message)
+            ciphertext,
+            MessageMac::from(Mac([0u8; Mac::LENGTH])),
+        )
     }
 
     pub(super) fn encrypt_truncated_mac(
@@ -192,25 +288,81 @@ impl MegolmMessage {
     ) -> Self {
         let ciphertext = cipher.encrypt(plaintext);
 
-        let message = Self {
-            version: MAC_TRUNCATED_VERSION,
+        Self::encrypt_helper(
+            cipher,
+            signing_key,
+            Self::MAC_TRUNCATED_VERSION,
+            message_index,
+            ciphertext,
+            MessageMac::from([0u8; Mac::TRUNCATED_LEN]),
+        )
+    }
+
+    /// Encrypt the plaintext using AES-256-GCM, the authentication tag takes
+    /// the place of the MAC.
+    #[cfg(feature = "gcm")]
+    pub(super) fn encrypt_gcm(
+        message_index: u32,
+        cipher: &Cipher,
+        signing_key: &Ed25519Keypair,
+        plaintext: &[u8],
+    ) -> Self {
+        let (ciphertext, tag) = cipher.encrypt_gcm(&Self::gcm_nonce(message_index), plaintext);
+
+        let mut message = Self {
+            version: Self::GCM_VERSION,
             ciphertext,
             message_index,
-            mac: [0u8; Mac::TRUNCATED_LEN].into(),
+            mac: MegolmMac::Gcm(tag),
             signature: Ed25519Signature::from_slice(&[0; Ed25519Signature::LENGTH])
                 .expect("Can't create an empty signature"),
         };
 
-        Self::encrypt_helper(cipher, signing_key, message)
+        message.signature = signing_key.sign(&message.to_signature_bytes());
+
+        message
     }
 
+    /// Derive the AES-GCM nonce for the given message index.
+    ///
+    /// Every message index uses its own AES key, the nonce only needs to be
+    /// unique per key. It's the big-endian message index, left padded with
+    /// zeros.
+    #[cfg(feature = "gcm")]
+    pub(super) fn gcm_nonce(message_index: u32) -> [u8; GCM_NONCE_LEN] {
+        let mut nonce = [0u8; GCM_NONCE_LEN];
+        nonce[GCM_NONCE_LEN - 4..].copy_from_slice(&message_index.to_be_bytes());
+
+        nonce
+    }
+
+    /// Authenticate the ciphertext with a HMAC and sign the message.
+    ///
+    /// The given placeholder MAC decides if the HMAC gets truncated, its
+    /// contents are replaced.
     fn encrypt_helper(
         cipher: &Cipher,
         signing_key: &Ed25519Keypair,
-        mut message: MegolmMessage,
+        version: u8,
+        message_index: u32,
+        ciphertext: Vec<u8>,
+        mac: MessageMac,
     ) -> Self {
-        let mac = cipher.mac(&message.to_mac_bytes());
-        message.set_mac(mac);
+        let mut message = Self {
+            version,
+            ciphertext,
+            message_index,
+            mac: mac.clone().into(),
+            signature: Ed25519Signature::from_slice(&[0; Ed25519Signature::LENGTH])
+                .expect("Can't create an empty signature"),
+        };
+
+        let hmac = cipher.mac(&message.to_mac_bytes());
+        let mac = match mac {
+            MessageMac::Truncated(_) => MessageMac::from(hmac.truncate()),
+            MessageMac::Full(_) => MessageMac::from(hmac),
+        };
+        message.mac = mac.into();
 
         let signature = signing_key.sign(&message.to_signature_bytes());
         message.signature = signature;
@@ -228,8 +380,29 @@ impl MegolmMessage {
 
         message
     }
+
+    /// Check the version and length of an encoded message and return the
+    /// protocol buffer payload sitting between the version byte and the MAC.
+    fn payload(message: &[u8]) -> Result<&[u8], DecodeError> {
+        let version = *message.first().ok_or(DecodeError::MissingVersion)?;
+
+        let suffix_length = match version {
+            Self::VERSION => Self::MESSAGE_SUFFIX_LENGTH,
+            Self::MAC_TRUNCATED_VERSION => Self::MESSAGE_TRUNCATED_SUFFIX_LENGTH,
+            #[cfg(feature = "gcm")]
+            Self::GCM_VERSION => Self::MESSAGE_GCM_SUFFIX_LENGTH,
+            _ => return Err(DecodeError::InvalidVersion(Self::VERSION, version)),
+        };
+
+        if message.len() < suffix_length + 2 {
+            Err(DecodeError::MessageTooShort(message.len()))
+        } else {
+            Ok(&message[1..message.len() - suffix_length])
+        }
+    }
 }
 
+#[cfg(feature = "serde")]
 impl Serialize for MegolmMessage {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
     where
@@ -240,6 +413,7 @@ impl Serialize for MegolmMessage {
     }
 }
 
+#[cfg(feature = "serde")]
 impl<'de> Deserialize<'de> for MegolmMessage {
     fn deserialize<D: serde::Deserializer<'de>>(d: D) -> Result<Self, D::Error> {
         let ciphertext = String::deserialize(d)?;
@@ -269,38 +443,32 @@ impl TryFrom<&[u8]> for MegolmMessage {
     type Error = DecodeError;
 
     fn try_from(message: &[u8]) -> Result<Self, Self::Error> {
-        let version = *message.first().ok_or(DecodeError::MissingVersion)?;
-
-        let suffix_length = match version {
-            VERSION => Self::MESSAGE_SUFFIX_LENGTH,
-            MAC_TRUNCATED_VERSION => Self::MESSAGE_TRUNCATED_SUFFIX_LENGTH,
-            _ => return Err(DecodeError::InvalidVersion(VERSION, version)),
+        let payload = Self::payload(message)?;
+        let inner = ProtobufMegolmMessage::decode(payload)?;
+
+        let version = message[0];
+        let suffix_length = message.len() - payload.len() - 1;
+
+        let signature_location = message.len() - Ed25519Signature::LENGTH;
+        let signature_slice = &message[signature_location..];
+        let signature = Ed25519Signature::from_slice(signature_slice)?;
+
+        let mac_slice = &message[message.len() - suffix_length..];
+        let mac = match version {
+            #[cfg(feature = "gcm")]
+            Self::GCM_VERSION => MegolmMac::Gcm(
+                mac_slice[..GCM_TAG_LEN].try_into().expect("The MAC slice contains the whole tag"),
+            ),
+            _ => extract_mac(mac_slice, version == Self::MAC_TRUNCATED_VERSION).into(),
         };
 
-        if message.len() < suffix_length + 2 {
-            Err(DecodeError::MessageTooShort(message.len()))
-        } else {
-            let inner = ProtobufMegolmMessage::decode(
-                message
-                    .get(1..message.len() - suffix_length)
-                    .ok_or_else(|| DecodeError::MessageTooShort(message.len()))?,
-            )?;
-
-            let signature_location = message.len() - Ed25519Signature::LENGTH;
-            let signature_slice = &message[signature_location..];
-            let signature = Ed25519Signature::from_slice(signature_slice)?;
-
-            let mac_slice = &message[message.len() - suffix_length..];
-            let mac = extract_mac(mac_slice, version == MAC_TRUNCATED_VERSION);
-
-            Ok(MegolmMessage {
-                version,
-                ciphertext: inner.ciphertext,
-                message_index: inner.message_index,
-                mac,
-                signature,
-            })
-        }
+        Ok(MegolmMessage {
+            version,
+            ciphertext: inner.ciphertext,
+            message_index: inner.message_index,
+            mac,
+            signature,
+        })
     }
 }
 
@@ -316,6 +484,27 @@ impl ProtobufMegolmMessage {
     const INDEX_TAG: &'static [u8; 1] = b"\x08";
     const CIPHER_TAG: &'static [u8; 1] = b"\x12";
 
+    /// Walk over the fields of an encoded payload and pick out the message
+    /// index, skipping over the ciphertext instead of copying it.
+    ///
+    /// Like a full decode, a missing index is treated as 0 and the last index
+    /// wins if there are multiple.
+    fn peek_index(mut payload: &[u8]) -> Result<u32, ProtoBufDecodeError> {
+        let mut message_index = 0;
+
+        while !payload.is_empty() {
+            let (tag, wire_type) = encoding::decode_key(&mut payload)?;
+
+            if tag == 1 && wire_type == WireType::Varint {
+                message_index = encoding::decode_varint(&mut payload)? as u32;
+            } else {
+                encoding::skip_field(wire_type, tag, &mut payload, DecodeContext::default())?;
+            }
+        }
+
+        Ok(message_index)
+    }
+
     fn encode_manual(&self, version: u8) -> Vec<u8> {
         // Prost optimizes away the message index if it's 0, libolm can't decode
         // this, so encode our messages the pedestrian way instead.
@@ -333,3 +522,67 @@ impl ProtobufMegolmMessage {
         .concat()
     }
 }
+
+#[cfg(test)]
+mod test {
+    use assert_matches::assert_matches;
+
+    use super::MegolmMessage;
+    use crate::{megolm::GroupSession, utilities::base64_encode, DecodeError, Ed25519Signature};
+
+    #[test]
+    fn message_index_is_a_varint() {
+        let signature = Ed25519Signature::from_slice(&[0u8; Ed25519Signature::LENGTH])
+            .expect("An all-zero signature should be decodable");
+        let ciphertext = [0u8; 16];
+
+        let bytes = MegolmMessage::encode(&ciphertext, 300, &[0u8; 32], &signature);
+
+        assert_eq!(bytes[..5], [MegolmMessage::VERSION, 0x08, 0xAC, 0x02, 0x12]);
+
+        let message = MegolmMessage::from_bytes(&bytes)
+            .expect("We should be able to decode a well formed message");
+        assert_eq!(message.message_index(), 300);
+
+        let bytes = MegolmMessage::encode(&ciphertext, 0x0102_0304, &[0u8; 32], &signature);
+
+        assert_eq!(bytes[..6], [MegolmMessage::VERSION, 0x08, 0x84, 0x86, 0x88, 0x08]);
+
+        let message = MegolmMessage::from_bytes(&bytes)
+            .expect("We should be able to decode a well formed message");
+        assert_eq!(message.message_index(), 0x0102_0304);
+    }
+
+    #[test]
+    fn peeking_at_the_message_index() {
+        let mut session = GroupSession::new(Default::default());
+
+        for _ in 0..300 {
+            let message = session
+                .encrypt("It's a secret to everybody")
+                .expect("The session should be able to encrypt");
+
+            assert_eq!(
+                MegolmMessage::peek_index(&message.to_base64())
+                    .expect("We should be able to peek at the index of a valid message"),
+                message.message_index()
+            );
+        }
+
+        let signature = Ed25519Signature::from_slice(&[0u8; Ed25519Signature::LENGTH])
+            .expect("An all-zero signature should be decodable");
+        let bytes = MegolmMessage::encode(&[0u8; 16], 0x0102_0304, &[0u8; 32], &signature);
+
+        assert_eq!(
+            MegolmMessage::peek_index(&base64_encode(&bytes))
+                .expect("We should be able to peek at the index of a forged message"),
+            0x0102_0304
+        );
+
+        assert_matches!(
+            MegolmMessage::peek_index(&base64_encode(&bytes[..40])),
+            Err(DecodeError::MessageTooShort(40))
+        );
+        assert_matches!(MegolmMessage::peek_index("!!!"), Err(DecodeError::Base64(_)));
+    }
+}
@@ -13,6 +13,52 @@
 // limitations under the License.
 
 //! An implementation of the Megolm ratchet.
+//!
+//! ## Overview
+//!
+//! A Megolm session is split into two halves. The sending participant owns a
+//! `GroupSession`, also known as the *outbound* group session, which is used
+//! to encrypt messages. Every receiving participant owns an
+//! `InboundGroupSession`, which can only be used to decrypt messages.
+//!
+//! An `InboundGroupSession` is created from the `SessionKey` of the
+//! `GroupSession`, which is typically sent to the receiving participants over
+//! an Olm channel. Sessions that were exported with an `ExportedSessionKey`,
+//! for example as part of a key backup, can be imported as well. Since such
+//! keys aren't signed, the imported session won't be considered to be
+//! verified.
+//!
+//! Decoding a session key fails with a `SessionKeyDecodeError`, decrypting a
+//! `MegolmMessage` fails with a `DecryptionError`. Encrypting only fails with
+//! an `EncryptError` once the message index of a `GroupSession` is exhausted.
+//!
+//! ```rust
+//! use anyhow::Result;
+//! use vodozemac::megolm::{
+//!     DecryptedMessage, DecryptionError, EncryptError, GroupSession, InboundGroupSession,
+//!     MegolmMessage, SessionConfig, SessionKey, SessionKeyDecodeError,
+//! };
+//!
+//! fn main() -> Result<()> {
+//!     let mut outbound = GroupSession::new(SessionConfig::version_2());
+//!
+//!     let session_key: Result<SessionKey, SessionKeyDecodeError> =
+//!         SessionKey::from_base64(&outbound.session_key().to_base64());
+//!     let mut inbound = InboundGroupSession::new(&session_key?, SessionConfig::version_2());
+//!
+//!     let message: Result<MegolmMessage, EncryptError> =
+//!         outbound.encrypt("It's a secret to everybody");
+//!     let message = message?;
+//!
+//!     let decrypted: Result<DecryptedMessage, DecryptionError> = inbound.decrypt(&message);
+//!     let decrypted = decrypted?;
+//!
+//!     assert_eq!(decrypted.plaintext, b"It's a secret to everybody");
+//!     assert_eq!(decrypted.message_index, 0);
+//!
+//!     Ok(())
+//! }
+//! ```
 
 mod group_session;
 mod inbound_group_session;
@@ -21,22 +67,75 @@ mod ratchet;
 mod session_config;
 mod session_keys;
 
-pub use group_session::{GroupSession, GroupSessionPickle};
+#[cfg(feature = "serde")]
+pub use group_session::GroupSessionPickle;
+pub use group_session::{EncryptError, GroupSession};
+#[cfg(feature = "rayon")]
+pub use inbound_group_session::decrypt_batch;
+#[cfg(feature = "serde")]
+pub use inbound_group_session::InboundGroupSessionPickle;
 pub use inbound_group_session::{
-    DecryptedMessage, DecryptionError, InboundGroupSession, InboundGroupSessionPickle,
-    SessionOrdering,
+    decrypt_any, DecryptedMessage, DecryptionError, ImportResult, InboundGroupSession,
+    MessageMetadata, SessionOrdering, VerificationResult,
 };
 pub use message::MegolmMessage;
 pub use session_config::SessionConfig;
-pub use session_keys::{ExportedSessionKey, SessionKey, SessionKeyDecodeError};
+pub use session_keys::{
+    ExportedSessionKey, SessionKey, SessionKeyDecodeError, SessionKeyErrorCategory,
+};
 
+#[cfg(feature = "dangerous-export")]
+pub use crate::cipher::MessageKeys;
+use crate::{Ed25519PublicKey, Ed25519Signature, SignatureError};
+
+/// The version byte every encoded [`SessionKey`] starts with.
+///
+/// This is the same version libolm uses for the keys produced by
+/// `olm_outbound_group_session_key()`, session keys can be freely exchanged
+/// between vodozemac and libolm. Exported session keys use
+/// [`ExportedSessionKey::VERSION`] instead.
+pub const SESSION_KEY_VERSION: u8 = SessionKey::VERSION;
+
+/// The version byte of Megolm messages using a full MAC.
+///
+/// Messages encrypted by a [`SessionConfig::version_1()`] session, the only
+/// format libolm understands, use [`MegolmMessage::MAC_TRUNCATED_VERSION`]
+/// instead.
+pub const MESSAGE_VERSION: u8 = MegolmMessage::VERSION;
+
+#[cfg(feature = "serde")]
 fn default_config() -> SessionConfig {
     SessionConfig::version_1()
 }
 
+/// Verify the signature of a Megolm session key or message.
+#[cfg(not(feature = "no-verify"))]
+fn verify_signature(
+    signing_key: &Ed25519PublicKey,
+    message: &[u8],
+    signature: &Ed25519Signature,
+) -> Result<(), SignatureError> {
+    signing_key.verify(message, signature)
+}
+
+/// A verify_signature function that always succeeds.
+///
+/// Used if the `no-verify` feature is enabled, the signature verification is
+/// then expected to happen outside of vodozemac.
+#[cfg(feature = "no-verify")]
+fn verify_signature(
+    _signing_key: &Ed25519PublicKey,
+    _message: &[u8],
+    _signature: &Ed25519Signature,
+) -> Result<(), SignatureError> {
+    Ok(())
+}
+
 #[cfg(feature = "libolm-compat")]
 mod libolm {
-    use matrix_pickle::Decode;
+    use std::io::Write;
+
+    use matrix_pickle::{Decode, Encode, EncodeError};
     use zeroize::Zeroize;
 
     use super::ratchet::Ratchet;
@@ -49,11 +148,40 @@ mod libolm {
         index: u32,
     }
 
+    impl Encode for LibolmRatchetPickle {
+        fn encode(&self, writer: &mut impl Write) -> Result<usize, EncodeError> {
+            Ok(self.ratchet.as_ref().encode(writer)? + self.index.encode(writer)?)
+        }
+    }
+
     impl From<&LibolmRatchetPickle> for Ratchet {
         fn from(pickle: &LibolmRatchetPickle) -> Self {
             Ratchet::from_bytes(pickle.ratchet.clone(), pickle.index)
         }
     }
+
+    impl From<&Ratchet> for LibolmRatchetPickle {
+        fn from(ratchet: &Ratchet) -> Self {
+            Self { ratchet: Box::new(*ratchet.as_bytes()), index: ratchet.index() }
+        }
+    }
+
+    /// The libolm pickle of an [`InboundGroupSession`].
+    ///
+    /// [`InboundGroupSession`]: super::InboundGroupSession
+    #[derive(Zeroize, Encode, Decode)]
+    #[zeroize(drop)]
+    pub(crate) struct LibolmInboundGroupSessionPickle {
+        pub version: u32,
+        pub initial_ratchet: LibolmRatchetPickle,
+        pub latest_ratchet: LibolmRatchetPickle,
+        pub signing_key: [u8; 32],
+        pub signing_key_verified: bool,
+    }
+
+    impl LibolmInboundGroupSessionPickle {
+        pub const VERSION: u32 = 2;
+    }
 }
 
 #[cfg(test)]
@@ -64,12 +192,18 @@ mod test {
         outbound_group_session::OlmOutboundGroupSession,
     };
 
-    use super::{ExportedSessionKey, GroupSession, InboundGroupSession, MegolmMessage};
+    use super::{
+        ExportedSessionKey, GroupSession, InboundGroupSession, MegolmMessage, MESSAGE_VERSION,
+        SESSION_KEY_VERSION,
+    };
+    #[cfg(feature = "serde")]
+    use crate::megolm::{GroupSessionPickle, InboundGroupSessionPickle};
     use crate::{
-        megolm::{GroupSessionPickle, InboundGroupSessionPickle, SessionConfig, SessionKey},
+        megolm::{SessionConfig, SessionKey},
         run_corpus,
     };
 
+    #[cfg(feature = "serde")]
     const PICKLE_KEY: [u8; 32] = [0u8; 32];
 
     #[test]
@@ -80,20 +214,23 @@ mod test {
         let olm_session = OlmInboundGroupSession::new(&session_key.to_base64())?;
 
         let plaintext = "It's a secret to everybody";
-        let message = session.encrypt(plaintext).to_base64();
+        let message =
+            session.encrypt(plaintext).expect("The session should be able to encrypt").to_base64();
 
         let (decrypted, _) = olm_session.decrypt(message)?;
 
         assert_eq!(decrypted, plaintext);
 
         let plaintext = "Another secret";
-        let message = session.encrypt(plaintext).to_base64();
+        let message =
+            session.encrypt(plaintext).expect("The session should be able to encrypt").to_base64();
 
         let (decrypted, _) = olm_session.decrypt(message)?;
         assert_eq!(decrypted, plaintext);
 
         let plaintext = "And another secret";
-        let message = session.encrypt(plaintext).to_base64();
+        let message =
+            session.encrypt(plaintext).expect("The session should be able to encrypt").to_base64();
         let (decrypted, _) = olm_session.decrypt(message)?;
 
         assert_eq!(decrypted, plaintext);
@@ -101,10 +238,11 @@ mod test {
         let plaintext = "Last secret";
 
         for _ in 1..2000 {
-            session.encrypt(plaintext);
+            session.encrypt(plaintext).expect("The session should be able to encrypt");
         }
 
-        let message = session.encrypt(plaintext).to_base64();
+        let message =
+            session.encrypt(plaintext).expect("The session should be able to encrypt").to_base64();
         let (decrypted, _) = olm_session.decrypt(message)?;
 
         assert_eq!(decrypted, plaintext);
@@ -172,9 +310,11 @@ mod test {
         assert_eq!(session.session_id(), inbound.session_id());
 
         let first_plaintext = "It's a secret to everybody".as_bytes();
-        let first_message = session.encrypt(first_plaintext);
+        let first_message =
+            session.encrypt(first_plaintext).expect("The session should be able to encrypt");
         let second_plaintext = "It's dangerous to go alone. Take this!".as_bytes();
-        let second_message = session.encrypt(second_plaintext);
+        let second_message =
+            session.encrypt(second_plaintext).expect("The session should be able to encrypt");
 
         let decrypted = inbound.decrypt(&first_message)?;
 
@@ -202,6 +342,73 @@ mod test {
     }
 
     #[test]
+    fn message_format_matches_config() {
+        #[cfg(not(feature = "gcm"))]
+        let configs = [SessionConfig::version_1(), SessionConfig::version_2()];
+        #[cfg(feature = "gcm")]
+        let configs =
+            [SessionConfig::version_1(), SessionConfig::version_2(), SessionConfig::gcm()];
+
+        for config in configs {
+            let mut session = GroupSession::new(config);
+            let message = session
+                .encrypt("It's a secret to everybody")
+                .expect("The session should be able to encrypt");
+
+            assert_eq!(message.mac().len(), config.mac_length());
+            assert_eq!(message.to_bytes()[0], config.message_version());
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "gcm")]
+    fn gcm_encryption_roundtrip() -> Result<()> {
+        let mut outbound = GroupSession::new(SessionConfig::gcm());
+        let mut inbound = InboundGroupSession::new(&outbound.session_key(), SessionConfig::gcm());
+
+        for index in 0..3 {
+            let plaintext = format!("It's a secret to everybody {index}");
+            let message = outbound.encrypt(&plaintext)?;
+            let bytes = message.to_bytes();
+
+            assert_eq!(bytes[0], MegolmMessage::GCM_VERSION);
+            assert_eq!(bytes.len(), 1 + 2 + 2 + plaintext.len() + 16 + 64);
+
+            let message = MegolmMessage::from_base64(&message.to_base64())?;
+            let decrypted = inbound.decrypt(&message)?;
+
+            assert_eq!(decrypted.plaintext, plaintext.as_bytes());
+            assert_eq!(decrypted.message_index, index);
+            assert_eq!(decrypted.mac.as_slice(), message.mac());
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    #[cfg(feature = "gcm")]
+    fn gcm_messages_are_only_decrypted_by_gcm_sessions() -> Result<()> {
+        use super::DecryptionError;
+
+        let mut outbound = GroupSession::new(SessionConfig::gcm());
+        let mut inbound = InboundGroupSession::new(&outbound.session_key(), Default::default());
+
+        let message = outbound.encrypt("It's a secret to everybody")?;
+        let error = inbound.decrypt(&message).expect_err("The MAC length doesn't match");
+        assert!(matches!(error, DecryptionError::InvalidMACLength(32, 16)));
+
+        let mut outbound = GroupSession::new(Default::default());
+        let mut inbound = InboundGroupSession::new(&outbound.session_key(), SessionConfig::gcm());
+
+        let message = outbound.encrypt("It's a secret to everybody")?;
+        let error = inbound.decrypt(&message).expect_err("The MAC length doesn't match");
+        assert!(matches!(error, DecryptionError::InvalidMACLength(16, 32)));
+
+        Ok(())
+    }
+
+    #[test]
+    #[cfg(feature = "serde")]
     fn group_session_pickling_roundtrip_is_identity() -> Result<()> {
         let session = GroupSession::new(Default::default());
 
@@ -223,6 +430,7 @@ mod test {
     }
 
     #[test]
+    #[cfg(feature = "serde")]
     fn inbound_group_session_pickling_roundtrip_is_identity() -> Result<()> {
         let session = GroupSession::new(Default::default());
         let session = InboundGroupSession::from(&session);
@@ -263,6 +471,30 @@ mod test {
         Ok(())
     }
 
+    #[test]
+    #[cfg(feature = "libolm-compat")]
+    fn libolm_inbound_pickling() -> Result<()> {
+        let mut outbound = GroupSession::new(SessionConfig::version_1());
+        let mut session = InboundGroupSession::from(&outbound);
+        let message = outbound
+            .encrypt("It's a secret to everybody")
+            .expect("The session should be able to encrypt");
+        session.decrypt(&message)?;
+
+        let key = b"DEFAULT_PICKLE_KEY";
+        let pickle = session.to_libolm_pickle(key)?;
+
+        let olm = OlmInboundGroupSession::unpickle(
+            pickle,
+            olm_rs::PicklingMode::Encrypted { key: key.to_vec() },
+        )?;
+
+        assert_eq!(olm.session_id(), session.session_id());
+        assert_eq!(olm.first_known_index(), session.first_known_index());
+
+        Ok(())
+    }
+
     #[test]
     #[cfg(feature = "libolm-compat")]
     fn libolm_unpickling() -> Result<()> {
@@ -280,7 +512,7 @@ mod test {
         assert_eq!(olm.session_message_index(), unpickled.message_index());
 
         let plaintext = "It's a secret to everybody".as_bytes();
-        let message = unpickled.encrypt(plaintext);
+        let message = unpickled.encrypt(plaintext).expect("The session should be able to encrypt");
 
         let decrypted = inbound_session.decrypt(&message)?;
 
@@ -290,6 +522,61 @@ mod test {
         Ok(())
     }
 
+    #[test]
+    fn public_version_constants() {
+        let mut session = GroupSession::new(SessionConfig::version_2());
+
+        assert_eq!(session.session_key().to_bytes()[0], SESSION_KEY_VERSION);
+        let message = session
+            .encrypt("It's a secret to everybody")
+            .expect("The session should be able to encrypt");
+        assert_eq!(message.to_bytes()[0], MESSAGE_VERSION);
+
+        let mut session = GroupSession::new(SessionConfig::version_1());
+        let message = session
+            .encrypt("It's a secret to everybody")
+            .expect("The session should be able to encrypt");
+        assert_eq!(message.to_bytes()[0], MegolmMessage::MAC_TRUNCATED_VERSION);
+    }
+
+    #[test]
+    fn encrypted_message_records_its_index() {
+        let mut session = GroupSession::new(Default::default());
+
+        for expected_index in 0..3 {
+            let message = session
+                .encrypt("It's a secret to everybody")
+                .expect("The session should be able to encrypt");
+
+            assert_eq!(message.message_index(), expected_index);
+            assert_eq!(session.message_index(), expected_index + 1);
+
+            let message = MegolmMessage::from_base64(&message.to_base64())
+                .expect("The encrypted message should decode");
+            assert_eq!(message.message_index(), expected_index);
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "serde")]
+    fn session_rotation() {
+        let week = std::time::Duration::from_secs(60 * 60 * 24 * 7);
+        let mut session = GroupSession::new(Default::default());
+
+        assert!(!session.should_rotate(2, week));
+        assert!(session.should_rotate(2, std::time::Duration::ZERO));
+
+        session.encrypt("First").expect("The session should be able to encrypt");
+        assert!(!session.should_rotate(2, week));
+        session.encrypt("Second").expect("The session should be able to encrypt");
+        assert!(session.should_rotate(2, week));
+
+        let session = GroupSession::from_pickle(session.pickle());
+        assert!(session.should_rotate(2, week));
+        assert!(!session.should_rotate(3, week));
+        assert!(session.should_rotate(3, std::time::Duration::ZERO));
+    }
+
     #[test]
     fn fuzz_corpus_decoding() {
         run_corpus("megolm-decoding", |data| {
@@ -15,16 +15,24 @@
 
 use hmac::{Hmac, Mac as _};
 use rand::{thread_rng, RngCore};
+#[cfg(feature = "serde")]
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use sha2::{digest::CtOutput, Sha256};
 use subtle::{Choice, ConstantTimeEq};
+#[cfg(feature = "serde")]
 use thiserror::Error;
 use zeroize::Zeroize;
 
 const ADVANCEMENT_SEEDS: [&[u8; 1]; Ratchet::RATCHET_PART_COUNT] =
     [b"\x00", b"\x01", b"\x02", b"\x03"];
 
-#[derive(Serialize, Deserialize, Zeroize, Clone)]
+/// The Megolm ratchet.
+///
+/// Both the ratchet and its bytes zeroize themselves when dropped. This
+/// includes the many short lived clones that get created while we look for
+/// the ratchet of a given message index.
+#[derive(Zeroize, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 #[zeroize(drop)]
 pub(super) struct Ratchet {
     inner: RatchetBytes,
@@ -46,6 +54,7 @@ impl ConstantTimeEq for Ratchet {
 #[zeroize(drop)]
 struct RatchetBytes(Box<[u8; Ratchet::RATCHET_LENGTH]>);
 
+#[cfg(feature = "serde")]
 impl RatchetBytes {
     fn from_bytes(bytes: &[u8]) -> Result<Self, RatchetBytesError> {
         let length = bytes.len();
@@ -61,6 +70,7 @@ impl RatchetBytes {
     }
 }
 
+#[cfg(feature = "serde")]
 impl Serialize for RatchetBytes {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
     where
@@ -71,6 +81,7 @@ impl Serialize for RatchetBytes {
     }
 }
 
+#[cfg(feature = "serde")]
 impl<'d> Deserialize<'d> for RatchetBytes {
     fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
     where
@@ -147,6 +158,11 @@ impl Ratchet {
         ratchet
     }
 
+    /// Create a ratchet from its raw bytes and index.
+    ///
+    /// The length of the ratchet is guaranteed by the array type. Every path
+    /// that reads a ratchet from a slice, session keys and pickles, checks the
+    /// length before the ratchet is created.
     pub fn from_bytes(bytes: Box<[u8; Self::RATCHET_LENGTH]>, counter: u32) -> Self {
         Self { inner: RatchetBytes(bytes), counter }
     }
@@ -159,6 +175,15 @@ impl Ratchet {
         &self.inner.0
     }
 
+    /// Copy the ratchet bytes into a buffer that is zeroized when dropped.
+    ///
+    /// Knowing the bytes is equivalent to being able to decrypt every message
+    /// from the current index of the ratchet onwards.
+    #[cfg(feature = "dangerous-export")]
+    pub fn to_zeroizing_bytes(&self) -> zeroize::Zeroizing<Vec<u8>> {
+        zeroize::Zeroizing::new(self.inner.0.to_vec())
+    }
+
     fn as_parts(&mut self) -> RatchetParts<'_> {
         let (top, bottom) = self.inner.0.split_at_mut(64);
 
@@ -245,8 +270,52 @@ impl Ratchet {
             self.counter = advance_to & mask;
         }
     }
+
+    /// Create a copy of this ratchet which has been advanced to the given
+    /// index, leaving this ratchet untouched.
+    #[cfg(any(test, feature = "low-level-api", feature = "dangerous-export"))]
+    pub fn advanced_to(&self, advance_to: u32) -> Self {
+        let mut ratchet = self.clone();
+        ratchet.advance_to(advance_to);
+
+        ratchet
+    }
+}
+
+/// An iterator over a ratchet and all of its successors.
+///
+/// The ratchet is advanced one step at a time, only when the next item is
+/// requested. The iterator ends once the ratchet reaches the maximal index.
+#[cfg(feature = "dangerous-export")]
+pub(super) struct RatchetIter {
+    next: Option<Ratchet>,
+}
+
+#[cfg(feature = "dangerous-export")]
+impl RatchetIter {
+    pub fn new(ratchet: Ratchet) -> Self {
+        Self { next: Some(ratchet) }
+    }
+}
+
+#[cfg(feature = "dangerous-export")]
+impl Iterator for RatchetIter {
+    type Item = Ratchet;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let ratchet = self.next.take()?;
+
+        if ratchet.index() < u32::MAX {
+            let mut next = ratchet.clone();
+            next.advance();
+            self.next = Some(next);
+        }
+
+        Some(ratchet)
+    }
 }
 
+#[cfg(feature = "serde")]
 #[derive(Error, Debug)]
 enum RatchetBytesError {
     #[error("Invalid Megolm ratchet length: expected 128, got {0}")]
@@ -270,4 +339,71 @@ mod tests {
         ratchet.counter = (1 << 24) - 1;
         ratchet.advance_to(1 << 24);
     }
+
+    #[test]
+    #[cfg(feature = "dangerous-export")]
+    fn ratchet_iterator_matches_advance_to() {
+        use super::RatchetIter;
+
+        let ratchet = Ratchet::new();
+
+        for (step, advanced) in RatchetIter::new(ratchet.clone()).take(300).enumerate() {
+            let step = step as u32;
+
+            assert_eq!(advanced.index(), step);
+            assert_eq!(advanced.as_bytes(), ratchet.advanced_to(step).as_bytes());
+        }
+
+        let last = Ratchet::from_bytes(Box::new([0; 128]), u32::MAX);
+        assert_eq!(RatchetIter::new(last).count(), 1);
+    }
+
+    #[test]
+    fn advanced_to_doesnt_mutate_the_ratchet() {
+        let ratchet = Ratchet::new();
+        let initial_bytes = *ratchet.as_bytes();
+
+        let advanced = ratchet.advanced_to(300);
+
+        assert_eq!(ratchet.index(), 0);
+        assert_eq!(ratchet.as_bytes(), &initial_bytes);
+
+        let mut expected = ratchet.clone();
+        expected.advance_to(300);
+
+        assert_eq!(advanced.index(), 300);
+        assert!(bool::from(advanced.ct_eq(&expected)));
+    }
+
+    #[test]
+    #[cfg(feature = "serde")]
+    fn pickled_ratchets_need_to_have_the_exact_length() {
+        for length in [0, 127, 129] {
+            let pickle = serde_json::json!({ "inner": vec![0u8; length], "counter": 0 });
+            let error = serde_json::from_value::<Ratchet>(pickle)
+                .err()
+                .expect("A ratchet with an invalid length should be rejected");
+
+            assert!(error.to_string().contains(&format!("expected 128, got {length}")));
+        }
+
+        let pickle = serde_json::json!({ "inner": vec![0u8; 128], "counter": 5 });
+        let ratchet: Ratchet =
+            serde_json::from_value(pickle).expect("A ratchet with 128 bytes should be accepted");
+        assert_eq!(ratchet.index(), 5);
+    }
+
+    #[test]
+    fn zeroizing_a_clone_leaves_the_original_intact() {
+        let ratchet = Ratchet::new();
+        let initial_bytes = *ratchet.as_bytes();
+
+        let mut clone = ratchet.clone();
+        clone.advance_to(10);
+        clone.zeroize();
+
+        assert_eq!(clone.index(), 0);
+        assert_eq!(clone.as_bytes(), &[0u8; Ratchet::RATCHET_LENGTH]);
+        assert_eq!(ratchet.as_bytes(), &initial_bytes);
+    }
 }
@@ -12,27 +12,90 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+#[cfg(feature = "serde")]
 use serde::{Deserialize, Serialize};
 
+use super::{ratchet::Ratchet, MegolmMessage};
+#[cfg(feature = "gcm")]
+use crate::cipher::GCM_TAG_LEN;
+use crate::cipher::{Cipher, Mac};
+
 /// A struct to configure how Megolm sessions should work under the hood.
-/// Currently only the MAC truncation behaviour can be configured.
-#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq)]
+/// Currently the MAC truncation behaviour can be configured, and with the
+/// `gcm` feature the cipher that encrypts individual messages.
+///
+/// The config isn't part of the session key, the sender and all the receivers
+/// of a session need to agree on it out of band. An [`InboundGroupSession`]
+/// refuses to decrypt messages whose MAC length doesn't match its config, a
+/// session using the full MAC will never accept a truncated one.
+///
+/// [`InboundGroupSession`]: crate::megolm::InboundGroupSession
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct SessionConfig {
     pub(super) version: Version,
 }
 
-#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub(super) enum Version {
     V1 = 1,
     V2 = 2,
+    #[cfg(feature = "gcm")]
+    Gcm = 3,
 }
 
 impl SessionConfig {
     /// Get the numeric version of this `SessionConfig`.
+    ///
+    /// This is `1` for [`SessionConfig::version_1()`] and `2` for
+    /// [`SessionConfig::version_2()`]. With the `gcm` feature,
+    /// `SessionConfig::gcm()` has the version `3`.
+    ///
+    /// The version of the config isn't the version of the session key. All
+    /// configs share the same session key format, see [`SessionKey::VERSION`].
+    ///
+    /// [`SessionKey::VERSION`]: crate::megolm::SessionKey::VERSION
     pub fn version(&self) -> u8 {
         self.version as u8
     }
 
+    /// Get the version byte of the Megolm messages that sessions using this
+    /// `SessionConfig` produce.
+    ///
+    /// This is [`MegolmMessage::MAC_TRUNCATED_VERSION`] for
+    /// [`SessionConfig::version_1()`] and [`MegolmMessage::VERSION`] for
+    /// [`SessionConfig::version_2()`].
+    pub fn message_version(&self) -> u8 {
+        match self.version {
+            Version::V1 => MegolmMessage::MAC_TRUNCATED_VERSION,
+            Version::V2 => MegolmMessage::VERSION,
+            #[cfg(feature = "gcm")]
+            Version::Gcm => MegolmMessage::GCM_VERSION,
+        }
+    }
+
+    /// Get the length of the message authentication code, in bytes, that
+    /// Megolm messages use with this `SessionConfig`.
+    pub fn mac_length(&self) -> usize {
+        match self.version {
+            Version::V1 => Mac::TRUNCATED_LEN,
+            Version::V2 => Mac::LENGTH,
+            #[cfg(feature = "gcm")]
+            Version::Gcm => GCM_TAG_LEN,
+        }
+    }
+
+    /// Create the [`Cipher`] that encrypts the message at the index of the
+    /// given ratchet.
+    pub(super) fn cipher(&self, ratchet: &Ratchet) -> Cipher {
+        match self.version {
+            Version::V1 | Version::V2 => Cipher::new_megolm(ratchet.as_bytes()),
+            #[cfg(feature = "gcm")]
+            Version::Gcm => Cipher::new_megolm_gcm(ratchet.as_bytes()),
+        }
+    }
+
     /// Create a `SessionConfig` for the Megolm version 1. This version of
     /// Megolm uses AES-256 and HMAC with a truncated MAC to encrypt individual
     /// messages. The MAC will be truncated to 8 bytes.
@@ -46,6 +109,27 @@ impl SessionConfig {
     pub fn version_2() -> Self {
         SessionConfig { version: Version::V2 }
     }
+
+    /// Create a `SessionConfig` which uses AES-256-GCM to encrypt individual
+    /// messages. The 16 byte authentication tag of AES-GCM takes the place of
+    /// the MAC.
+    ///
+    /// The ratchet, the session keys and the message signatures are the same
+    /// as in the other versions, but the messages use their own version byte,
+    /// [`MegolmMessage::GCM_VERSION`]. The nonce is derived from the message
+    /// index, the AES key is derived from the ratchet separately from the keys
+    /// of the other versions.
+    ///
+    /// **Warning**: This mode isn't part of the Megolm specification, libolm
+    /// and other Megolm implementations can't decrypt these messages. Only use
+    /// it if every participant of the session uses vodozemac with the `gcm`
+    /// feature.
+    ///
+    /// [`MegolmMessage::GCM_VERSION`]: crate::megolm::MegolmMessage::GCM_VERSION
+    #[cfg(feature = "gcm")]
+    pub fn gcm() -> Self {
+        SessionConfig { version: Version::Gcm }
+    }
 }
 
 impl Default for SessionConfig {
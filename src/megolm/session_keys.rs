@@ -12,37 +12,99 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use std::io::{Cursor, Read};
-
+#[cfg(feature = "serde")]
 use serde::{Deserialize, Serialize};
 use thiserror::Error;
 use zeroize::Zeroize;
 
-use super::ratchet::Ratchet;
+use super::{ratchet::Ratchet, verify_signature};
 use crate::{
-    utilities::{base64_decode, base64_encode},
+    utilities::{base64_decode, base64_encode, unpadded_base64_len, DecodeError},
     Ed25519PublicKey, Ed25519Signature, SignatureError,
 };
 
 /// Error type describing failure modes for the `SessionKey` and
 /// `ExportedSessionKey` decoding.
 #[derive(Debug, Error)]
+#[non_exhaustive]
 pub enum SessionKeyDecodeError {
     /// The encoded session key had a unsupported version.
     #[error("The session key had a invalid version, expected {0}, got {1}")]
     Version(u8, u8),
-    /// The encoded session key didn't contain enough data to be decoded.
-    #[error("The session key was too short {0}")]
-    Read(#[from] std::io::Error),
+    /// The encoded session key was truncated, or had trailing data after the
+    /// key.
+    #[error("The session key had an invalid length, expected {0} bytes, got {1}")]
+    Length(usize, usize),
     /// The encoded session key wasn't valid base64.
     #[error("The session key wasn't valid base64: {0}")]
-    Base64(#[from] base64::DecodeError),
-    /// The signature on the session key was invalid.
-    #[error("The signature on the session key was invalid: {0}")]
-    Signature(#[from] SignatureError),
+    Base64(#[from] DecodeError),
+    /// The signature on the session key was malformed or couldn't be verified
+    /// using the signing key contained in the session key. The signing key is
+    /// public and safe to log.
+    #[error("The signature on the session key was invalid for the signing key {0}: {1}")]
+    Signature(Ed25519PublicKey, #[source] SignatureError),
     /// The encoded session key contains an invalid public key.
     #[error("The public key of session was invalid: {0}")]
     PublicKey(#[from] crate::KeyError),
+    /// The base64 encoded session key was longer than any valid session key
+    /// can be.
+    #[error("The session key was too long, expected at most {0} characters, got {1}")]
+    TooLong(usize, usize),
+}
+
+/// The broad category of a [`SessionKeyDecodeError`].
+///
+/// Useful to count failures without matching on every single error variant.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum SessionKeyErrorCategory {
+    /// The session key had an unsupported version.
+    Version,
+    /// The session key was too short or too long.
+    Length,
+    /// The session key wasn't valid base64.
+    Base64,
+    /// The signature of the session key was malformed or invalid.
+    Signature,
+    /// The session key contained an invalid public key.
+    PublicKey,
+}
+
+impl SessionKeyDecodeError {
+    /// Get the [`SessionKeyErrorCategory`] of this error.
+    pub fn category(&self) -> SessionKeyErrorCategory {
+        match self {
+            SessionKeyDecodeError::Version(..) => SessionKeyErrorCategory::Version,
+            SessionKeyDecodeError::Length(..) | SessionKeyDecodeError::TooLong(..) => {
+                SessionKeyErrorCategory::Length
+            }
+            SessionKeyDecodeError::Base64(_) => SessionKeyErrorCategory::Base64,
+            SessionKeyDecodeError::Signature(..) => SessionKeyErrorCategory::Signature,
+            SessionKeyDecodeError::PublicKey(_) => SessionKeyErrorCategory::PublicKey,
+        }
+    }
+}
+
+/// Check that the base64 encoded form of a key with the given decoded length
+/// isn't longer than it can be, before we spend any memory on decoding it.
+fn check_base64_length(key: &str, decoded_length: usize) -> Result<(), SessionKeyDecodeError> {
+    let max_length = unpadded_base64_len(decoded_length);
+
+    if key.len() > max_length {
+        Err(SessionKeyDecodeError::TooLong(max_length, key.len()))
+    } else {
+        Ok(())
+    }
+}
+
+/// Split off the first `count` bytes of the given slice.
+///
+/// The caller needs to check that the slice is long enough.
+fn take<'a>(bytes: &mut &'a [u8], count: usize) -> &'a [u8] {
+    let (head, tail) = bytes.split_at(count);
+    *bytes = tail;
+
+    head
 }
 
 /// The exported session key.
@@ -56,7 +118,12 @@ pub struct ExportedSessionKey {
 }
 
 impl ExportedSessionKey {
-    const VERSION: u8 = 1;
+    /// The version byte of an encoded `ExportedSessionKey`.
+    ///
+    /// This is the only version of the export format libolm and vodozemac
+    /// have used, keys with any other version are rejected.
+    pub const VERSION: u8 = 1;
+    const LENGTH: usize = 1 + 4 + Ratchet::RATCHET_LENGTH + Ed25519PublicKey::LENGTH;
 
     pub(super) fn new(ratchet: &Ratchet, signing_key: Ed25519PublicKey) -> Self {
         let ratchet_index = ratchet.index();
@@ -80,9 +147,8 @@ impl ExportedSessionKey {
     }
 
     /// Deserialize the `ExportedSessionKey` from a byte slice.
-    pub fn from_bytes(bytes: &[u8]) -> Result<Self, SessionKeyDecodeError> {
-        let mut cursor = Cursor::new(bytes);
-        Self::decode_key(Self::VERSION, &mut cursor)
+    pub fn from_bytes(mut bytes: &[u8]) -> Result<Self, SessionKeyDecodeError> {
+        Self::decode_key(Self::VERSION, Self::LENGTH, &mut bytes)
     }
 
     /// Serialize the `ExportedSessionKey` to a base64 encoded string.
@@ -102,6 +168,8 @@ impl ExportedSessionKey {
 
     /// Deserialize the `ExportedSessionKey` from base64 encoded string.
     pub fn from_base64(key: &str) -> Result<Self, SessionKeyDecodeError> {
+        check_base64_length(key, Self::LENGTH)?;
+
         let mut bytes = base64_decode(key)?;
         let ret = Self::from_bytes(&bytes);
 
@@ -110,29 +178,38 @@ impl ExportedSessionKey {
         ret
     }
 
+    /// Decode a key from the start of the given slice, leaving the slice
+    /// pointing at the remaining bytes.
+    ///
+    /// The slice needs to contain exactly `expected_length` bytes, this
+    /// includes any data the caller is going to read after the key. Trailing
+    /// data is rejected, otherwise it would shift the signed part of a
+    /// [`SessionKey`]. The ratchet is copied out of the slice only once the
+    /// whole key has been validated.
     fn decode_key(
         expected_version: u8,
-        cursor: &mut Cursor<&[u8]>,
+        expected_length: usize,
+        bytes: &mut &[u8],
     ) -> Result<ExportedSessionKey, SessionKeyDecodeError> {
-        let mut version = [0u8; 1];
-        let mut index = [0u8; 4];
-        let mut ratchet = Box::new([0u8; 128]);
-        let mut public_key = [0u8; Ed25519PublicKey::LENGTH];
+        let version = *bytes.first().ok_or(SessionKeyDecodeError::Length(expected_length, 0))?;
 
-        cursor.read_exact(&mut version)?;
+        if version != expected_version {
+            return Err(SessionKeyDecodeError::Version(expected_version, version));
+        } else if bytes.len() != expected_length {
+            return Err(SessionKeyDecodeError::Length(expected_length, bytes.len()));
+        }
 
-        if version[0] != expected_version {
-            Err(SessionKeyDecodeError::Version(expected_version, version[0]))
-        } else {
-            cursor.read_exact(&mut index)?;
-            cursor.read_exact(ratchet.as_mut_slice())?;
-            cursor.read_exact(&mut public_key)?;
+        take(bytes, 1);
+        let index = take(bytes, 4);
+        let ratchet_bytes = take(bytes, Ratchet::RATCHET_LENGTH);
+        let signing_key = Ed25519PublicKey::from_slice(take(bytes, Ed25519PublicKey::LENGTH))?;
 
-            let signing_key = Ed25519PublicKey::from_slice(&public_key)?;
-            let ratchet_index = u32::from_be_bytes(index);
+        let ratchet_index =
+            u32::from_be_bytes(index.try_into().expect("We took exactly four bytes"));
+        let mut ratchet = Box::new([0u8; Ratchet::RATCHET_LENGTH]);
+        ratchet.copy_from_slice(ratchet_bytes);
 
-            Ok(ExportedSessionKey { ratchet_index, ratchet, signing_key })
-        }
+        Ok(ExportedSessionKey { ratchet_index, ratchet, signing_key })
     }
 }
 
@@ -165,6 +242,7 @@ impl TryFrom<&str> for ExportedSessionKey {
     }
 }
 
+#[cfg(feature = "serde")]
 impl Serialize for ExportedSessionKey {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
     where
@@ -179,6 +257,7 @@ impl Serialize for ExportedSessionKey {
     }
 }
 
+#[cfg(feature = "serde")]
 impl<'de> Deserialize<'de> for ExportedSessionKey {
     fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
     where
@@ -218,7 +297,16 @@ pub struct SessionKey {
 }
 
 impl SessionKey {
-    const VERSION: u8 = 2;
+    /// The version byte of an encoded `SessionKey`.
+    ///
+    /// This is the only version of the session key format libolm and
+    /// vodozemac have used, keys with any other version are rejected. The
+    /// [`SessionConfig`] of a session doesn't change the format of its session
+    /// key.
+    ///
+    /// [`SessionConfig`]: super::SessionConfig
+    pub const VERSION: u8 = 2;
+    const LENGTH: usize = ExportedSessionKey::LENGTH + Ed25519Signature::LENGTH;
 
     pub(super) fn new(ratchet: &Ratchet, signing_key: Ed25519PublicKey) -> Self {
         let session_key = ExportedSessionKey::new(ratchet, signing_key);
@@ -244,19 +332,21 @@ impl SessionKey {
 
     /// Deserialize the `SessionKey` from a byte slice.
     pub fn from_bytes(bytes: &[u8]) -> Result<Self, SessionKeyDecodeError> {
-        let mut cursor = Cursor::new(bytes);
-        let session_key = ExportedSessionKey::decode_key(Self::VERSION, &mut cursor)?;
-
-        let mut signature = [0u8; Ed25519Signature::LENGTH];
-
-        cursor.read_exact(&mut signature)?;
-        let signature = Ed25519Signature::from_slice(&signature)?;
-
-        let decoded = cursor.into_inner();
-
-        session_key
-            .signing_key
-            .verify(&decoded[..decoded.len() - Ed25519Signature::LENGTH], &signature)?;
+        let mut remaining = bytes;
+        let session_key =
+            ExportedSessionKey::decode_key(Self::VERSION, Self::LENGTH, &mut remaining)?;
+        let invalid_signature = |e| SessionKeyDecodeError::Signature(session_key.signing_key, e);
+
+        let signature =
+            Ed25519Signature::from_slice(take(&mut remaining, Ed25519Signature::LENGTH))
+                .map_err(invalid_signature)?;
+
+        verify_signature(
+            &session_key.signing_key,
+            &bytes[..bytes.len() - Ed25519Signature::LENGTH],
+            &signature,
+        )
+        .map_err(invalid_signature)?;
 
         Ok(Self { session_key, signature })
     }
@@ -277,6 +367,8 @@ impl SessionKey {
 
     /// Deserialize the `SessionKey` from base64 encoded string.
     pub fn from_base64(key: &str) -> Result<Self, SessionKeyDecodeError> {
+        check_base64_length(key, Self::LENGTH)?;
+
         let mut bytes = base64_decode(key)?;
         let ret = Self::from_bytes(&bytes);
 
@@ -314,6 +406,7 @@ impl TryFrom<&str> for SessionKey {
     }
 }
 
+#[cfg(feature = "serde")]
 impl Serialize for SessionKey {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
     where
@@ -328,6 +421,7 @@ impl Serialize for SessionKey {
     }
 }
 
+#[cfg(feature = "serde")]
 impl<'de> Deserialize<'de> for SessionKey {
     fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
     where
@@ -344,9 +438,16 @@ impl<'de> Deserialize<'de> for SessionKey {
 
 #[cfg(test)]
 mod test {
-    use crate::megolm::{ExportedSessionKey, GroupSession, InboundGroupSession, SessionKey};
+    use crate::{
+        megolm::{
+            ExportedSessionKey, GroupSession, InboundGroupSession, SessionKey,
+            SessionKeyDecodeError, SessionKeyErrorCategory,
+        },
+        Ed25519Keypair, Ed25519PublicKey,
+    };
 
     #[test]
+    #[cfg(feature = "serde")]
     fn session_key_serialization() -> Result<(), anyhow::Error> {
         let session = GroupSession::new(Default::default());
 
@@ -364,6 +465,7 @@ mod test {
     }
 
     #[test]
+    #[cfg(feature = "serde")]
     fn exported_session_key_serialization() -> Result<(), anyhow::Error> {
         let session = GroupSession::new(Default::default());
         let mut session = InboundGroupSession::from(&session);
@@ -381,4 +483,216 @@ mod test {
 
         Ok(())
     }
+
+    #[test]
+    fn overly_long_keys_are_rejected_before_decoding() {
+        let session = GroupSession::new(Default::default());
+        let session_key = session.session_key();
+        let exported_key = InboundGroupSession::from(&session).export_at_first_known_index();
+
+        assert_eq!(session_key.to_bytes().len(), SessionKey::LENGTH);
+        assert_eq!(exported_key.to_bytes().len(), ExportedSessionKey::LENGTH);
+
+        SessionKey::from_base64(&session_key.to_base64())
+            .expect("A session key of the maximal length should be accepted");
+        ExportedSessionKey::from_base64(&exported_key.to_base64())
+            .expect("An exported session key of the maximal length should be accepted");
+
+        let too_long = "A".repeat(1024 * 1024);
+
+        assert!(matches!(
+            SessionKey::from_base64(&too_long),
+            Err(SessionKeyDecodeError::TooLong(306, 1_048_576))
+        ));
+        assert!(matches!(
+            ExportedSessionKey::from_base64(&too_long),
+            Err(SessionKeyDecodeError::TooLong(220, 1_048_576))
+        ));
+    }
+
+    #[test]
+    #[cfg(not(feature = "no-verify"))]
+    fn invalid_signature_errors_contain_the_signing_key() {
+        let session = GroupSession::new(Default::default());
+        let mut key = session.session_key().to_bytes();
+
+        // Flip a bit in the ratchet, the signature won't match anymore.
+        key[10] ^= 1;
+
+        let error = SessionKey::from_bytes(&key)
+            .err()
+            .expect("A session key with an invalid signature should be rejected");
+
+        assert!(matches!(
+            &error,
+            SessionKeyDecodeError::Signature(signing_key, _)
+                if signing_key.to_base64() == session.session_id()
+        ));
+        assert!(error.to_string().contains(&session.session_id()));
+    }
+
+    #[test]
+    fn truncated_or_invalid_keys_fail_to_decode() {
+        let session = GroupSession::new(Default::default());
+        let key = InboundGroupSession::from(&session).export_at_first_known_index().to_bytes();
+
+        assert!(matches!(
+            ExportedSessionKey::from_bytes(&key[..key.len() - 1]),
+            Err(SessionKeyDecodeError::Length(165, 164))
+        ));
+
+        let mut invalid_key = key.clone();
+        invalid_key[key.len() - Ed25519PublicKey::LENGTH..].copy_from_slice(&[2u8; 32]);
+
+        assert!(matches!(
+            ExportedSessionKey::from_bytes(&invalid_key),
+            Err(SessionKeyDecodeError::PublicKey(_))
+        ));
+    }
+
+    #[test]
+    fn every_truncation_of_a_session_key_fails_to_decode() {
+        let session = GroupSession::new(Default::default());
+        let key = session.session_key().to_bytes();
+
+        assert!(matches!(SessionKey::from_bytes(&[]), Err(SessionKeyDecodeError::Length(229, 0))));
+
+        for length in 1..key.len() {
+            assert!(
+                matches!(
+                    SessionKey::from_bytes(&key[..length]),
+                    Err(SessionKeyDecodeError::Length(229, l)) if l == length
+                ),
+                "A session key truncated to {length} bytes should fail to decode"
+            );
+        }
+
+        SessionKey::from_bytes(&key).expect("The full session key should decode");
+    }
+
+    #[test]
+    fn ratchets_of_the_wrong_length_are_rejected() {
+        let session = GroupSession::new(Default::default());
+        let key = InboundGroupSession::from(&session).export_at_first_known_index().to_bytes();
+        let (header, rest) = key.split_at(1 + 4);
+        let (ratchet, signing_key) = rest.split_at(128);
+
+        let short = [header, &ratchet[..127], signing_key].concat();
+        let long = [header, ratchet, &[0], signing_key].concat();
+
+        assert!(matches!(
+            ExportedSessionKey::from_bytes(&short),
+            Err(SessionKeyDecodeError::Length(165, 164))
+        ));
+        assert!(matches!(
+            ExportedSessionKey::from_bytes(&long),
+            Err(SessionKeyDecodeError::Length(165, 166))
+        ));
+    }
+
+    #[test]
+    fn trailing_data_is_rejected() {
+        let session = GroupSession::new(Default::default());
+        let mut session_key = session.session_key().to_bytes();
+        let mut exported_key =
+            InboundGroupSession::from(&session).export_at_first_known_index().to_bytes();
+
+        session_key.push(0);
+        exported_key.push(0);
+
+        assert!(matches!(
+            SessionKey::from_bytes(&session_key),
+            Err(SessionKeyDecodeError::Length(229, 230))
+        ));
+        assert!(matches!(
+            ExportedSessionKey::from_bytes(&exported_key),
+            Err(SessionKeyDecodeError::Length(165, 166))
+        ));
+
+        session_key.extend([0; 63]);
+
+        assert!(matches!(
+            SessionKey::from_bytes(&session_key),
+            Err(SessionKeyDecodeError::Length(229, 293))
+        ));
+    }
+
+    #[test]
+    fn errors_are_sorted_into_categories() {
+        let session = GroupSession::new(Default::default());
+        let key = session.session_key().to_bytes();
+        let category = |bytes: &[u8]| {
+            SessionKey::from_bytes(bytes)
+                .err()
+                .expect("The session key should be rejected")
+                .category()
+        };
+
+        assert_eq!(category(&key[..10]), SessionKeyErrorCategory::Length);
+
+        let mut wrong_version = key.clone();
+        wrong_version[0] = 1;
+        assert_eq!(category(&wrong_version), SessionKeyErrorCategory::Version);
+
+        let mut invalid_public_key = key.clone();
+        invalid_public_key[1 + 4 + 128..1 + 4 + 128 + 32].copy_from_slice(&[2u8; 32]);
+        assert_eq!(category(&invalid_public_key), SessionKeyErrorCategory::PublicKey);
+
+        #[cfg(not(feature = "no-verify"))]
+        {
+            let mut invalid_signature = key.clone();
+            invalid_signature[10] ^= 1;
+            assert_eq!(category(&invalid_signature), SessionKeyErrorCategory::Signature);
+        }
+
+        let error = SessionKey::from_base64("A=").err().expect("Padded base64 should be rejected");
+        assert_eq!(error.category(), SessionKeyErrorCategory::Base64);
+
+        let error = SessionKey::from_base64(&"A".repeat(1024))
+            .err()
+            .expect("An overly long key should be rejected");
+        assert_eq!(error.category(), SessionKeyErrorCategory::Length);
+    }
+
+    #[test]
+    fn ratchet_index_is_big_endian() {
+        let signing_key = Ed25519Keypair::new().public_key();
+
+        let bytes = [
+            [ExportedSessionKey::VERSION].as_ref(),
+            &[0x01, 0x02, 0x03, 0x04],
+            &[0u8; 128],
+            signing_key.as_bytes(),
+        ]
+        .concat();
+
+        let key = ExportedSessionKey::from_bytes(&bytes)
+            .expect("We should be able to decode a well formed exported session key");
+
+        assert_eq!(key.ratchet_index, 0x0102_0304);
+        assert_eq!(key.to_bytes(), bytes);
+
+        let session = InboundGroupSession::import(&key, Default::default());
+        assert_eq!(session.first_known_index(), 0x0102_0304);
+    }
+
+    #[test]
+    fn session_key_encodes_the_ratchet_index_as_big_endian() {
+        let mut session = GroupSession::new(Default::default());
+
+        for _ in 0..258 {
+            session.encrypt("Advance the ratchet").expect("The session should be able to encrypt");
+        }
+
+        let key = session.session_key();
+        let bytes = key.to_bytes();
+
+        assert_eq!(bytes[1..5], [0x00, 0x00, 0x01, 0x02]);
+
+        let key = SessionKey::from_bytes(&bytes)
+            .expect("We should be able to decode our own session key");
+        let session = InboundGroupSession::new(&key, Default::default());
+
+        assert_eq!(session.first_known_index(), 258);
+    }
 }
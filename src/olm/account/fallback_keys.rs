@@ -12,6 +12,7 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+#[cfg(feature = "serde")]
 use serde::{Deserialize, Serialize};
 
 use crate::{
@@ -19,7 +20,8 @@ use crate::{
     Curve25519PublicKey,
 };
 
-#[derive(Serialize, Deserialize, Clone)]
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub(super) struct FallbackKey {
     pub key_id: KeyId,
     pub key: Curve25519SecretKey,
@@ -54,7 +56,8 @@ impl FallbackKey {
     }
 }
 
-#[derive(Serialize, Deserialize, Clone)]
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub(super) struct FallbackKeys {
     pub key_id: u64,
     pub fallback_key: Option<FallbackKey>,
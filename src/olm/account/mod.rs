@@ -15,17 +15,17 @@
 mod fallback_keys;
 mod one_time_keys;
 
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 
 use rand::thread_rng;
+#[cfg(feature = "serde")]
 use serde::{Deserialize, Serialize};
 use thiserror::Error;
 use x25519_dalek::ReusableSecret;
 
-use self::{
-    fallback_keys::FallbackKeys,
-    one_time_keys::{OneTimeKeys, OneTimeKeysPickle},
-};
+#[cfg(feature = "serde")]
+use self::one_time_keys::OneTimeKeysPickle;
+use self::{fallback_keys::FallbackKeys, one_time_keys::OneTimeKeys};
 use super::{
     messages::PreKeyMessage,
     session::{DecryptionError, Session},
@@ -35,18 +35,39 @@ use super::{
 };
 use crate::{
     types::{
-        Curve25519Keypair, Curve25519KeypairPickle, Curve25519PublicKey, Curve25519SecretKey,
-        Ed25519Keypair, Ed25519KeypairPickle, Ed25519PublicKey, KeyId,
+        Curve25519Keypair, Curve25519PublicKey, Curve25519SecretKey, Ed25519Keypair,
+        Ed25519PublicKey, KeyId,
     },
+    Ed25519Signature,
+};
+#[cfg(feature = "serde")]
+use crate::{
+    types::{Curve25519KeypairPickle, Ed25519KeypairPickle},
     utilities::{pickle, unpickle},
-    Ed25519Signature, PickleError,
+    KeyError, PickleError,
 };
 
 const PUBLIC_MAX_ONE_TIME_KEYS: usize = 50;
 
+/// What an [`Account`] should do when generating new one-time keys while it
+/// already holds as many private one-time keys as it can store.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EvictionPolicy {
+    /// Forget the oldest one-time keys, by key ID, to make room for the new
+    /// ones. The private parts of the evicted keys are zeroized.
+    ///
+    /// Pre-key messages that still use an evicted key can't be decrypted
+    /// anymore.
+    DropOldest,
+    /// Don't generate more keys than fit into the store, existing one-time
+    /// keys are never forgotten.
+    Refuse,
+}
+
 /// Error describing failure modes when creating a Olm Session from an incoming
 /// Olm message.
 #[derive(Error, Debug)]
+#[non_exhaustive]
 pub enum SessionCreationError {
     /// The pre-key message contained an unknown one-time key. This happens
     /// either because we never had such a one-time key, or because it has
@@ -68,7 +89,22 @@ pub enum SessionCreationError {
 }
 
 /// Struct holding the two public identity keys of an [`Account`].
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+///
+/// The keys are serialized as unpadded base64 strings, which is the shape
+/// that is uploaded to the server as part of the device keys:
+///
+/// ```json
+/// {
+///     "ed25519": "<base64 encoded Ed25519 key>",
+///     "curve25519": "<base64 encoded Curve25519 key>"
+/// }
+/// ```
+///
+/// Keys serialized by vodozemac 0.3.0, which used arrays of bytes instead of
+/// base64 strings, can still be deserialized.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(try_from = "EncodedIdentityKeys", into = "IdentityKeysBase64"))]
 pub struct IdentityKeys {
     /// The ed25519 key, used for signing.
     pub ed25519: Ed25519PublicKey,
@@ -76,6 +112,98 @@ pub struct IdentityKeys {
     pub curve25519: Curve25519PublicKey,
 }
 
+#[cfg(feature = "serde")]
+#[derive(Serialize)]
+struct IdentityKeysBase64 {
+    ed25519: String,
+    curve25519: String,
+}
+
+#[cfg(feature = "serde")]
+impl From<IdentityKeys> for IdentityKeysBase64 {
+    fn from(keys: IdentityKeys) -> Self {
+        Self { ed25519: keys.ed25519.to_base64(), curve25519: keys.curve25519.to_base64() }
+    }
+}
+
+#[cfg(feature = "serde")]
+#[derive(Deserialize)]
+struct EncodedIdentityKeys {
+    ed25519: EncodedKey,
+    curve25519: EncodedKey,
+}
+
+/// A public key of a serialized [`IdentityKeys`] struct, either an unpadded
+/// base64 string or the array of bytes vodozemac 0.3.0 produced.
+#[cfg(feature = "serde")]
+enum EncodedKey {
+    Base64(String),
+    Bytes(Vec<u8>),
+}
+
+#[cfg(feature = "serde")]
+impl<'de> Deserialize<'de> for EncodedKey {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        struct KeyVisitor;
+
+        impl<'de> serde::de::Visitor<'de> for KeyVisitor {
+            type Value = EncodedKey;
+
+            fn expecting(&self, formatter: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                formatter.write_str("an unpadded base64 string or an array of bytes")
+            }
+
+            fn visit_str<E: serde::de::Error>(self, v: &str) -> Result<Self::Value, E> {
+                Ok(EncodedKey::Base64(v.to_owned()))
+            }
+
+            fn visit_bytes<E: serde::de::Error>(self, v: &[u8]) -> Result<Self::Value, E> {
+                Ok(EncodedKey::Bytes(v.to_vec()))
+            }
+
+            fn visit_seq<A>(self, mut seq: A) -> Result<Self::Value, A::Error>
+            where
+                A: serde::de::SeqAccess<'de>,
+            {
+                let mut bytes = Vec::new();
+
+                while let Some(byte) = seq.next_element()? {
+                    bytes.push(byte);
+                }
+
+                Ok(EncodedKey::Bytes(bytes))
+            }
+        }
+
+        if deserializer.is_human_readable() {
+            deserializer.deserialize_any(KeyVisitor)
+        } else {
+            deserializer.deserialize_str(KeyVisitor)
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+impl TryFrom<EncodedIdentityKeys> for IdentityKeys {
+    type Error = KeyError;
+
+    fn try_from(keys: EncodedIdentityKeys) -> Result<Self, Self::Error> {
+        let ed25519 = match keys.ed25519 {
+            EncodedKey::Base64(k) => Ed25519PublicKey::from_base64(&k)?,
+            EncodedKey::Bytes(k) => Ed25519PublicKey::from_slice(&k)?,
+        };
+        let curve25519 = match keys.curve25519 {
+            EncodedKey::Base64(k) => Curve25519PublicKey::from_base64(&k)?,
+            EncodedKey::Bytes(k) => Curve25519PublicKey::from_slice(&k)?,
+        };
+
+        Ok(Self { ed25519, curve25519 })
+    }
+}
+
 /// Return type for the creation of inbound [`Session`] objects.
 #[derive(Debug)]
 pub struct InboundCreationResult {
@@ -85,6 +213,17 @@ pub struct InboundCreationResult {
     pub plaintext: Vec<u8>,
 }
 
+/// The number of one-time keys an [`Account`] holds, split by whether they
+/// have been published.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct OneTimeKeyCounts {
+    /// The number of one-time keys that were marked as published using
+    /// [`Account::mark_keys_as_published()`].
+    pub published: usize,
+    /// The number of one-time keys that still need to be published.
+    pub unpublished: usize,
+}
+
 /// An Olm account manages all cryptographic keys used on a device.
 pub struct Account {
     /// A permanent Ed25519 key used for signing. Also known as the fingerprint
@@ -127,9 +266,47 @@ impl Account {
         self.diffie_hellman_key.public_key()
     }
 
+    /// Get the account's public Ed25519 key in its base64 encoded form.
+    ///
+    /// This is a shorthand for calling [`Ed25519PublicKey::to_base64()`] on
+    /// the result of [`Account::ed25519_key()`].
+    pub fn ed25519_key_base64(&self) -> String {
+        self.ed25519_key().to_base64()
+    }
+
+    /// Get the account's public Curve25519 key in its base64 encoded form.
+    ///
+    /// This is a shorthand for calling [`Curve25519PublicKey::to_base64()`]
+    /// on the result of [`Account::curve25519_key()`].
+    pub fn curve25519_key_base64(&self) -> String {
+        self.curve25519_key().to_base64()
+    }
+
     /// Sign the given message using our Ed25519 fingerprint key.
-    pub fn sign(&self, message: &str) -> Ed25519Signature {
-        self.signing_key.sign(message.as_bytes())
+    ///
+    /// The message can be any byte sequence, it doesn't need to be valid
+    /// UTF-8.
+    pub fn sign(&self, message: impl AsRef<[u8]>) -> Ed25519Signature {
+        self.signing_key.sign(message.as_ref())
+    }
+
+    /// Check if the given signature for the message was created by this
+    /// account's Ed25519 fingerprint key.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use vodozemac::olm::Account;
+    ///
+    /// let account = Account::new();
+    /// let signature = account.sign("It's dangerous to go alone");
+    ///
+    /// assert!(account.verify_own_signature(b"It's dangerous to go alone", &signature));
+    /// assert!(!account.verify_own_signature(b"Take this", &signature));
+    /// ```
+    #[cfg(feature = "testing")]
+    pub fn verify_own_signature(&self, message: &[u8], signature: &Ed25519Signature) -> bool {
+        self.ed25519_key().verify(message, signature).is_ok()
     }
 
     /// Get the maximum number of one-time keys the client should keep on the
@@ -152,6 +329,33 @@ impl Account {
     }
 
     /// Create a `Session` with the given identity key and one-time key.
+    ///
+    /// This performs the sender side of the Olm triple Diffie-Hellman
+    /// handshake, using our identity key and a freshly generated ephemeral
+    /// base key.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use vodozemac::olm::{Account, OlmMessage, SessionConfig};
+    ///
+    /// let alice = Account::new();
+    /// let mut bob = Account::new();
+    ///
+    /// bob.generate_one_time_keys(1);
+    /// let one_time_key = *bob.one_time_keys().values().next().unwrap();
+    ///
+    /// let mut alice_session = alice.create_outbound_session(
+    ///     SessionConfig::version_2(),
+    ///     bob.curve25519_key(),
+    ///     one_time_key,
+    /// );
+    ///
+    /// if let OlmMessage::PreKey(message) = alice_session.encrypt("Hello Bob") {
+    ///     let result = bob.create_inbound_session(alice.curve25519_key(), &message).unwrap();
+    ///     assert_eq!(result.plaintext, b"Hello Bob");
+    /// }
+    /// ```
     pub fn create_outbound_session(
         &self,
         session_config: SessionConfig,
@@ -273,8 +477,39 @@ impl Account {
     }
 
     /// Generates the supplied number of one time keys.
-    pub fn generate_one_time_keys(&mut self, count: usize) {
-        self.one_time_keys.generate(count);
+    ///
+    /// The number of generated keys is capped at
+    /// [`Account::max_number_of_one_time_keys()`], a `count` of `0` doesn't
+    /// generate any keys.
+    ///
+    /// Returns the public parts of the one-time keys that were generated by
+    /// this call, ready to be uploaded.
+    ///
+    /// Once the account holds the maximum number of private one-time keys it
+    /// can store, the oldest ones are dropped, see
+    /// [`EvictionPolicy::DropOldest`].
+    pub fn generate_one_time_keys(&mut self, count: usize) -> HashMap<KeyId, Curve25519PublicKey> {
+        self.generate_one_time_keys_with_policy(count, EvictionPolicy::DropOldest)
+    }
+
+    /// Generates the supplied number of one time keys, using the given
+    /// [`EvictionPolicy`] if the account can't store any more private one-time
+    /// keys.
+    ///
+    /// The number of generated keys is capped at
+    /// [`Account::max_number_of_one_time_keys()`]. With
+    /// [`EvictionPolicy::Refuse`] fewer keys, possibly none, are generated
+    /// once the store is full.
+    ///
+    /// Returns the public parts of the one-time keys that were generated by
+    /// this call.
+    pub fn generate_one_time_keys_with_policy(
+        &mut self,
+        count: usize,
+        policy: EvictionPolicy,
+    ) -> HashMap<KeyId, Curve25519PublicKey> {
+        let count = count.min(self.max_number_of_one_time_keys());
+        self.one_time_keys.generate(count, policy)
     }
 
     /// Get the currently unpublished one-time keys.
@@ -289,6 +524,158 @@ impl Account {
             .collect()
     }
 
+    /// Get the number of published and unpublished one-time keys this
+    /// [`Account`] holds.
+    ///
+    /// Keys which were used up to create an inbound [`Session`] or which were
+    /// removed from the account are not counted.
+    pub fn one_time_key_counts(&self) -> OneTimeKeyCounts {
+        let keys = &self.one_time_keys;
+        let total = keys.private_keys.len();
+        // Only count the unpublished keys we still hold the secret for, so a
+        // pickle with dangling public keys can't make the published count
+        // underflow.
+        let unpublished = keys
+            .unpublished_public_keys
+            .keys()
+            .filter(|id| keys.private_keys.contains_key(id))
+            .count();
+
+        OneTimeKeyCounts { published: total - unpublished, unpublished }
+    }
+
+    /// Get the currently unpublished one-time keys in the shape that is
+    /// uploaded to a Matrix homeserver.
+    ///
+    /// The map is keyed by the algorithm and key ID, i.e.
+    /// `curve25519:<key ID>`, and the values are the base64 encoded public
+    /// keys.
+    pub fn one_time_keys_base64(&self) -> BTreeMap<String, String> {
+        self.one_time_keys
+            .unpublished_public_keys
+            .iter()
+            .map(|(key_id, key)| (format!("curve25519:{}", String::from(*key_id)), key.to_base64()))
+            .collect()
+    }
+
+    /// Sign a one-time or fallback key, producing the signature of the
+    /// "signed curve25519" key object Matrix clients upload.
+    ///
+    /// The signed object is the canonical JSON form of `{"key": "<base64
+    /// public key>"}`, fallback keys additionally contain `"fallback": true`.
+    ///
+    /// The key ID only appears in the name the key object is uploaded under,
+    /// i.e. `signed_curve25519:<key ID>`, it isn't part of the signed object.
+    /// The signature doesn't depend on it, so this method doesn't take one.
+    pub fn sign_one_time_key(
+        &self,
+        public_key: Curve25519PublicKey,
+        fallback: bool,
+    ) -> Ed25519Signature {
+        // Canonical JSON sorts the fields, and base64 never needs escaping.
+        let object = if fallback {
+            format!(r#"{{"fallback":true,"key":"{}"}}"#, public_key.to_base64())
+        } else {
+            format!(r#"{{"key":"{}"}}"#, public_key.to_base64())
+        };
+
+        self.sign(object)
+    }
+
+    /// Get the currently unpublished one-time keys as signed key objects,
+    /// ready to be uploaded to a Matrix homeserver.
+    ///
+    /// The map is keyed by `signed_curve25519:<key ID>` and every value has
+    /// the form:
+    ///
+    /// ```json
+    /// {
+    ///     "key": "<base64 encoded public key>",
+    ///     "signatures": {
+    ///         "<user ID>": {
+    ///             "ed25519:<device ID>": "<base64 encoded signature>"
+    ///         }
+    ///     }
+    /// }
+    /// ```
+    #[cfg(feature = "serde")]
+    pub fn signed_one_time_keys(
+        &self,
+        user_id: &str,
+        device_id: &str,
+    ) -> BTreeMap<String, serde_json::Value> {
+        self.one_time_keys
+            .unpublished_public_keys
+            .iter()
+            .map(|(key_id, key)| {
+                (
+                    format!("signed_curve25519:{}", String::from(*key_id)),
+                    self.signed_key_object(*key, false, user_id, device_id),
+                )
+            })
+            .collect()
+    }
+
+    /// Get the currently unpublished fallback key as a signed key object, in
+    /// the same shape as [`Account::signed_one_time_keys()`] with an
+    /// additional `"fallback": true` field.
+    #[cfg(feature = "serde")]
+    pub fn signed_fallback_key(
+        &self,
+        user_id: &str,
+        device_id: &str,
+    ) -> BTreeMap<String, serde_json::Value> {
+        self.fallback_keys
+            .unpublished_fallback_key()
+            .map(|key| {
+                (
+                    format!("signed_curve25519:{}", String::from(key.key_id())),
+                    self.signed_key_object(key.public_key(), true, user_id, device_id),
+                )
+            })
+            .into_iter()
+            .collect()
+    }
+
+    /// The unsigned key object of a one-time or fallback key.
+    ///
+    /// The fields live in a `BTreeMap`, so they are serialized in the sorted
+    /// order canonical JSON requires.
+    #[cfg(feature = "serde")]
+    fn key_object(
+        public_key: Curve25519PublicKey,
+        fallback: bool,
+    ) -> BTreeMap<&'static str, serde_json::Value> {
+        let mut object = BTreeMap::from([("key", public_key.to_base64().into())]);
+
+        if fallback {
+            object.insert("fallback", true.into());
+        }
+
+        object
+    }
+
+    #[cfg(feature = "serde")]
+    fn signed_key_object(
+        &self,
+        public_key: Curve25519PublicKey,
+        fallback: bool,
+        user_id: &str,
+        device_id: &str,
+    ) -> serde_json::Value {
+        let signature = self.sign_one_time_key(public_key, fallback);
+        let mut object = Self::key_object(public_key, fallback);
+
+        object.insert(
+            "signatures",
+            serde_json::json!({
+                user_id: { format!("ed25519:{device_id}"): signature.to_base64() }
+            }),
+        );
+
+        object.into_iter().map(|(field, value)| (field.to_owned(), value)).collect()
+    }
+
     /// Generate a single new fallback key.
     ///
     /// The fallback key will be used by other users to establish a `Session` if
@@ -326,16 +713,18 @@ impl Account {
 
     /// Convert the account into a struct which implements [`serde::Serialize`]
     /// and [`serde::Deserialize`].
+    #[cfg(feature = "serde")]
     pub fn pickle(&self) -> AccountPickle {
         AccountPickle {
             signing_key: self.signing_key.clone().into(),
-            diffie_hellman_key: self.diffie_hellman_key.clone().into(),
+            diffie_hellman_key: self.diffie_hellman_key.to_pickle(),
             one_time_keys: self.one_time_keys.clone().into(),
             fallback_keys: self.fallback_keys.clone(),
         }
     }
 
     /// Restore an [`Account`] from a previously saved [`AccountPickle`].
+    #[cfg(feature = "serde")]
     pub fn from_pickle(pickle: AccountPickle) -> Self {
         pickle.into()
     }
@@ -380,6 +769,7 @@ impl Default for Account {
 
 /// A format suitable for serialization which implements [`serde::Serialize`]
 /// and [`serde::Deserialize`]. Obtainable by calling [`Account::pickle`].
+#[cfg(feature = "serde")]
 #[derive(Serialize, Deserialize)]
 pub struct AccountPickle {
     signing_key: Ed25519KeypairPickle,
@@ -390,6 +780,7 @@ pub struct AccountPickle {
 
 /// A format suitable for serialization which implements [`serde::Serialize`]
 /// and [`serde::Deserialize`]. Obtainable by calling [`Account::pickle`].
+#[cfg(feature = "serde")]
 impl AccountPickle {
     /// Serialize and encrypt the pickle using the given key.
     ///
@@ -407,6 +798,7 @@ impl AccountPickle {
     }
 }
 
+#[cfg(feature = "serde")]
 impl From<AccountPickle> for Account {
     fn from(pickle: AccountPickle) -> Self {
         Self {
@@ -539,16 +931,21 @@ mod test {
     use anyhow::{bail, Context, Result};
     use olm_rs::{account::OlmAccount, session::OlmMessage as LibolmOlmMessage};
 
-    use super::{Account, InboundCreationResult, SessionConfig, SessionCreationError};
+    #[cfg(feature = "serde")]
+    use super::IdentityKeys;
+    use super::{
+        Account, EvictionPolicy, InboundCreationResult, OneTimeKeyCounts, SessionConfig,
+        SessionCreationError,
+    };
+    #[cfg(feature = "serde")]
+    use crate::olm::AccountPickle;
     use crate::{
         cipher::Mac,
-        olm::{
-            messages::{OlmMessage, PreKeyMessage},
-            AccountPickle,
-        },
-        run_corpus, Curve25519PublicKey as PublicKey,
+        olm::messages::{OlmMessage, PreKeyMessage},
+        Curve25519PublicKey as PublicKey,
     };
 
+    #[cfg(feature = "serde")]
     const PICKLE_KEY: [u8; 32] = [0u8; 32];
 
     #[test]
@@ -676,6 +1073,81 @@ mod test {
         Ok(())
     }
 
+    #[test]
+    fn inbound_session_creation_removes_the_one_time_key() -> Result<()> {
+        let alice = Account::new();
+        let mut bob = Account::new();
+
+        bob.generate_one_time_keys(1);
+
+        let one_time_key =
+            *bob.one_time_keys().values().next().expect("Didn't find a valid one-time key");
+
+        let mut alice_session = alice.create_outbound_session(
+            SessionConfig::version_2(),
+            bob.curve25519_key(),
+            one_time_key,
+        );
+
+        let text = "It's a secret to everybody";
+
+        if let OlmMessage::PreKey(m) = alice_session.encrypt(text) {
+            let InboundCreationResult { session, plaintext } =
+                bob.create_inbound_session(alice.curve25519_key(), &m)?;
+
+            assert_eq!(alice_session.session_id(), session.session_id());
+            assert_eq!(text.as_bytes(), plaintext);
+            assert!(bob.one_time_keys.private_keys.is_empty());
+
+            match bob.create_inbound_session(alice.curve25519_key(), &m) {
+                Err(SessionCreationError::MissingOneTimeKey(key)) => {
+                    assert_eq!(key, one_time_key)
+                }
+                e => bail!("Expected a missing one-time key error, got {:?}", e),
+            }
+        } else {
+            bail!("Expected the first message to be a pre-key message");
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn evicted_one_time_keys_cant_be_used() -> Result<()> {
+        let alice = Account::new();
+        let mut bob = Account::new();
+
+        bob.generate_one_time_keys(1);
+        let one_time_key =
+            *bob.one_time_keys().values().next().expect("Didn't find a valid one-time key");
+        bob.mark_keys_as_published();
+
+        let mut alice_session = alice.create_outbound_session(
+            SessionConfig::version_2(),
+            bob.curve25519_key(),
+            one_time_key,
+        );
+
+        // Fill the store until the first one-time key gets evicted.
+        for _ in 0..100 {
+            bob.generate_one_time_keys(bob.max_number_of_one_time_keys());
+            bob.mark_keys_as_published();
+        }
+
+        if let OlmMessage::PreKey(m) = alice_session.encrypt("It's a secret to everybody") {
+            match bob.create_inbound_session(alice.curve25519_key(), &m) {
+                Err(SessionCreationError::MissingOneTimeKey(key)) => {
+                    assert_eq!(key, one_time_key)
+                }
+                e => bail!("Expected a missing one-time key error, got {:?}", e),
+            }
+        } else {
+            bail!("Expected the first message to be a pre-key message");
+        }
+
+        Ok(())
+    }
+
     #[test]
     fn inbound_session_creation() -> Result<()> {
         let alice = OlmAccount::new();
@@ -748,6 +1220,245 @@ mod test {
     }
 
     #[test]
+    fn one_time_key_generation_is_capped() {
+        let mut account = Account::new();
+        let max = account.max_number_of_one_time_keys();
+
+        assert!(account.generate_one_time_keys(0).is_empty());
+        assert!(account.one_time_keys().is_empty());
+
+        assert_eq!(account.generate_one_time_keys(max).len(), max);
+        assert_eq!(account.one_time_keys().len(), max);
+
+        account.mark_keys_as_published();
+
+        assert_eq!(account.generate_one_time_keys(max + 100).len(), max);
+        assert_eq!(account.one_time_keys().len(), max);
+        assert_eq!(account.one_time_keys.private_keys.len(), 2 * max);
+    }
+
+    #[test]
+    fn generating_one_time_keys_returns_only_the_new_keys() {
+        let mut account = Account::new();
+
+        let first = account.generate_one_time_keys(5);
+        assert_eq!(first.len(), 5);
+        assert_eq!(first, account.one_time_keys());
+
+        let second = account.generate_one_time_keys(3);
+        assert_eq!(second.len(), 3);
+        assert!(second.keys().all(|key_id| !first.contains_key(key_id)));
+
+        let all = account.one_time_keys();
+        assert_eq!(all.len(), 8);
+        assert!(second.iter().all(|(key_id, key)| all.get(key_id) == Some(key)));
+    }
+
+    #[test]
+    fn one_time_key_counts_track_publishing() -> Result<()> {
+        let mut account = Account::new();
+        assert_eq!(account.one_time_key_counts(), OneTimeKeyCounts::default());
+
+        account.generate_one_time_keys(5);
+        assert_eq!(
+            account.one_time_key_counts(),
+            OneTimeKeyCounts { published: 0, unpublished: 5 }
+        );
+
+        account.mark_keys_as_published();
+        account.generate_one_time_keys(2);
+        assert_eq!(
+            account.one_time_key_counts(),
+            OneTimeKeyCounts { published: 5, unpublished: 2 }
+        );
+
+        let published_key = *account
+            .one_time_keys
+            .key_ids_by_key
+            .keys()
+            .find(|key| !account.one_time_keys().values().any(|k| k == *key))
+            .expect("There should be a published one-time key");
+        account.remove_one_time_key_helper(published_key).expect("The key should be removable");
+        assert_eq!(
+            account.one_time_key_counts(),
+            OneTimeKeyCounts { published: 4, unpublished: 2 }
+        );
+
+        let unpublished_key =
+            *account.one_time_keys().values().next().expect("There should be an unpublished key");
+        account.remove_one_time_key_helper(unpublished_key).expect("The key should be removable");
+        assert_eq!(
+            account.one_time_key_counts(),
+            OneTimeKeyCounts { published: 4, unpublished: 1 }
+        );
+
+        let dangling_key_id = *account
+            .one_time_keys
+            .unpublished_public_keys
+            .keys()
+            .next()
+            .expect("There should be an unpublished key");
+        account.one_time_keys.private_keys.remove(&dangling_key_id);
+        assert_eq!(
+            account.one_time_key_counts(),
+            OneTimeKeyCounts { published: 4, unpublished: 0 }
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn one_time_key_eviction_policies() {
+        let mut account = Account::new();
+        let max = account.max_number_of_one_time_keys();
+
+        while !account.generate_one_time_keys_with_policy(max, EvictionPolicy::Refuse).is_empty() {
+            account.mark_keys_as_published();
+        }
+
+        let stored = account.one_time_keys.private_keys.len();
+        let oldest = account.one_time_keys.private_keys.keys().next().copied();
+
+        assert!(account.generate_one_time_keys_with_policy(1, EvictionPolicy::Refuse).is_empty());
+        assert!(account.one_time_keys().is_empty());
+
+        assert_eq!(
+            account.generate_one_time_keys_with_policy(1, EvictionPolicy::DropOldest).len(),
+            1
+        );
+        assert_eq!(account.one_time_keys().len(), 1);
+        assert_eq!(account.one_time_keys.private_keys.len(), stored);
+        assert_ne!(account.one_time_keys.private_keys.keys().next().copied(), oldest);
+    }
+
+    #[test]
+    #[cfg(feature = "serde")]
+    fn identity_keys_serialize_to_base64() -> Result<()> {
+        let account = Account::new();
+        let identity_keys = account.identity_keys();
+
+        let serialized = serde_json::to_value(identity_keys)?;
+
+        assert_eq!(
+            serialized,
+            serde_json::json!({
+                "ed25519": account.ed25519_key().to_base64(),
+                "curve25519": account.curve25519_key().to_base64(),
+            })
+        );
+
+        let deserialized: IdentityKeys = serde_json::from_value(serialized)?;
+        assert_eq!(deserialized, identity_keys);
+
+        let invalid = serde_json::json!({ "ed25519": "not base64!", "curve25519": "" });
+        assert!(serde_json::from_value::<IdentityKeys>(invalid).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    #[cfg(feature = "serde")]
+    fn identity_keys_deserialize_from_the_0_3_0_format() -> Result<()> {
+        // vodozemac 0.3.0 serialized both keys as arrays of bytes.
+        let legacy = serde_json::json!({
+            "ed25519": [
+                234, 74, 108, 99, 226, 156, 82, 10, 190, 245, 80, 123, 19, 46, 197, 249, 149, 71,
+                118, 174, 190, 190, 123, 146, 66, 30, 234, 105, 20, 70, 210, 44
+            ],
+            "curve25519": [
+                87, 219, 75, 53, 159, 35, 174, 94, 20, 110, 78, 37, 18, 5, 103, 4, 114, 37, 6, 52,
+                140, 21, 12, 20, 117, 61, 12, 147, 61, 4, 212, 33
+            ],
+        });
+
+        let identity_keys: IdentityKeys = serde_json::from_value(legacy)?;
+
+        assert_eq!(
+            identity_keys.ed25519.to_base64(),
+            "6kpsY+KcUgq+9VB7Ey7F+ZVHdq6+vnuSQh7qaRRG0iw"
+        );
+        assert_eq!(
+            identity_keys.curve25519.to_base64(),
+            "V9tLNZ8jrl4Ubk4lEgVnBHIlBjSMFQwUdT0Mkz0E1CE"
+        );
+        assert_eq!(
+            serde_json::to_value(identity_keys)?,
+            serde_json::json!({
+                "ed25519": "6kpsY+KcUgq+9VB7Ey7F+ZVHdq6+vnuSQh7qaRRG0iw",
+                "curve25519": "V9tLNZ8jrl4Ubk4lEgVnBHIlBjSMFQwUdT0Mkz0E1CE",
+            })
+        );
+
+        let too_short =
+            serde_json::json!({ "ed25519": vec![0u8; 31], "curve25519": vec![0u8; 32] });
+        assert!(serde_json::from_value::<IdentityKeys>(too_short).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn one_time_keys_base64_uses_the_upload_shape() {
+        let mut account = Account::new();
+        account.generate_one_time_keys(5);
+
+        let keys = account.one_time_keys_base64();
+
+        assert_eq!(keys.len(), 5);
+
+        for (key_id, key) in account.one_time_keys() {
+            let name = format!("curve25519:{}", key_id.to_base64());
+            assert_eq!(keys.get(&name), Some(&key.to_base64()));
+        }
+
+        account.mark_keys_as_published();
+        assert!(account.one_time_keys_base64().is_empty());
+    }
+
+    #[test]
+    #[cfg(feature = "serde")]
+    fn signed_one_time_keys() -> Result<()> {
+        let mut account = Account::new();
+        account.generate_one_time_keys(2);
+        account.generate_fallback_key();
+
+        let keys = account.signed_one_time_keys("@alice:example.org", "DEVICEID");
+        assert_eq!(keys.len(), 2);
+
+        for (key_id, key) in account.one_time_keys() {
+            let object = &keys[&format!("signed_curve25519:{}", key_id.to_base64())];
+            let signature = object["signatures"]["@alice:example.org"]["ed25519:DEVICEID"]
+                .as_str()
+                .expect("The key object should contain a signature");
+
+            assert_eq!(object["key"], key.to_base64());
+            assert!(object.get("fallback").is_none());
+
+            let canonical = format!(r#"{{"key":"{}"}}"#, key.to_base64());
+            account.ed25519_key().verify_base64(canonical.as_bytes(), signature)?;
+        }
+
+        let fallback_keys = account.signed_fallback_key("@alice:example.org", "DEVICEID");
+        let (key_id, key) =
+            account.fallback_key().into_iter().next().expect("We should have a fallback key");
+        let object = &fallback_keys[&format!("signed_curve25519:{}", key_id.to_base64())];
+        let signature = object["signatures"]["@alice:example.org"]["ed25519:DEVICEID"]
+            .as_str()
+            .expect("The fallback key object should contain a signature");
+
+        assert_eq!(object["fallback"], true);
+
+        let canonical = format!(r#"{{"fallback":true,"key":"{}"}}"#, key.to_base64());
+        account.ed25519_key().verify_base64(canonical.as_bytes(), signature)?;
+
+        account.mark_keys_as_published();
+        assert!(account.signed_one_time_keys("@alice:example.org", "DEVICEID").is_empty());
+        assert!(account.signed_fallback_key("@alice:example.org", "DEVICEID").is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    #[cfg(feature = "serde")]
     fn account_pickling_roundtrip_is_identity() -> Result<()> {
         let mut account = Account::new();
 
@@ -774,6 +1485,72 @@ mod test {
         Ok(())
     }
 
+    #[test]
+    #[cfg(feature = "libolm-compat")]
+    fn libolm_pickle_layout() -> Result<()> {
+        use std::collections::HashMap;
+
+        use crate::{types::Curve25519SecretKey, KeyId};
+
+        fn one_time_key(key_id: u32, published: bool, secret_key: &Curve25519SecretKey) -> Vec<u8> {
+            [
+                key_id.to_be_bytes().as_ref(),
+                &[published.into()],
+                PublicKey::from(secret_key).as_bytes(),
+                &secret_key.to_bytes(),
+            ]
+            .concat()
+        }
+
+        let identity_key = Curve25519SecretKey::from_slice(&[1u8; 32]);
+        let published_key = Curve25519SecretKey::from_slice(&[2u8; 32]);
+        let unpublished_key = Curve25519SecretKey::from_slice(&[3u8; 32]);
+        let fallback_key = Curve25519SecretKey::from_slice(&[4u8; 32]);
+
+        let pickle = [
+            // The pickle version.
+            4u32.to_be_bytes().as_ref(),
+            // The Ed25519 keypair, the public part is recomputed from the
+            // expanded secret key.
+            &[0u8; 32],
+            &[5u8; 64],
+            // The Curve25519 keypair.
+            PublicKey::from(&identity_key).as_bytes(),
+            &identity_key.to_bytes(),
+            // Two one-time keys, the first one has already been published.
+            &2u32.to_be_bytes(),
+            &one_time_key(10, true, &published_key),
+            &one_time_key(11, false, &unpublished_key),
+            // A single unpublished fallback key.
+            &[1u8],
+            &one_time_key(7, false, &fallback_key),
+            // The ID of the next one-time key.
+            &12u32.to_be_bytes(),
+        ]
+        .concat();
+
+        let mut account = Account::from_decrypted_libolm_pickle(&pickle)?;
+
+        assert_eq!(account.curve25519_key(), PublicKey::from(&identity_key));
+        assert_eq!(
+            account.one_time_keys(),
+            HashMap::from([(KeyId(11), PublicKey::from(&unpublished_key))])
+        );
+        assert_eq!(
+            account.fallback_key(),
+            HashMap::from([(KeyId(7), PublicKey::from(&fallback_key))])
+        );
+
+        account.mark_keys_as_published();
+        account.generate_one_time_keys(1);
+        assert!(account.one_time_keys().contains_key(&KeyId(12)));
+
+        account.generate_fallback_key();
+        assert!(account.fallback_key().contains_key(&KeyId(8)));
+
+        Ok(())
+    }
+
     #[test]
     #[cfg(feature = "libolm-compat")]
     fn libolm_unpickling() -> Result<()> {
@@ -817,6 +1594,19 @@ mod test {
         Ok(())
     }
 
+    #[test]
+    fn signing_binary_messages() {
+        let account = Account::new();
+        let message = [0xff, 0xfe, 0x00, 0x80];
+
+        let signature = account.sign(message);
+
+        account
+            .ed25519_key()
+            .verify(&message, &signature)
+            .expect("The signature of a non UTF-8 message should be valid");
+    }
+
     #[test]
     #[cfg(feature = "libolm-compat")]
     fn signing_with_expanded_key() -> Result<()> {
@@ -877,10 +1667,24 @@ mod test {
         }
     }
 
+    #[test]
+    #[cfg(feature = "serde")]
+    fn encoded_identity_keys() {
+        let account = Account::new();
+
+        assert_eq!(account.ed25519_key_base64(), account.ed25519_key().to_base64());
+        assert_eq!(account.curve25519_key_base64(), account.curve25519_key().to_base64());
+
+        let unpickled = Account::from_pickle(account.pickle());
+
+        assert_eq!(unpickled.ed25519_key_base64(), account.ed25519_key_base64());
+        assert_eq!(unpickled.curve25519_key_base64(), account.curve25519_key_base64());
+    }
+
     #[test]
     #[cfg(feature = "libolm-compat")]
     fn fuzz_corpus_unpickling() {
-        run_corpus("olm-account-unpickling", |data| {
+        crate::run_corpus("olm-account-unpickling", |data| {
             let _ = Account::from_decrypted_libolm_pickle(data);
         });
     }
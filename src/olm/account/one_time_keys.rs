@@ -14,17 +14,19 @@
 
 use std::collections::{BTreeMap, HashMap};
 
+#[cfg(feature = "serde")]
 use serde::{Deserialize, Serialize};
 
-use super::PUBLIC_MAX_ONE_TIME_KEYS;
+use super::{EvictionPolicy, PUBLIC_MAX_ONE_TIME_KEYS};
 use crate::{
     types::{Curve25519SecretKey, KeyId},
     Curve25519PublicKey,
 };
 
-#[derive(Serialize, Deserialize, Clone)]
-#[serde(from = "OneTimeKeysPickle")]
-#[serde(into = "OneTimeKeysPickle")]
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(from = "OneTimeKeysPickle"))]
+#[cfg_attr(feature = "serde", serde(into = "OneTimeKeysPickle"))]
 pub(super) struct OneTimeKeys {
     pub next_key_id: u64,
     pub unpublished_public_keys: BTreeMap<KeyId, Curve25519PublicKey>,
@@ -89,21 +91,38 @@ impl OneTimeKeys {
         }
     }
 
-    pub fn generate(&mut self, count: usize) {
+    pub fn generate(
+        &mut self,
+        count: usize,
+        policy: EvictionPolicy,
+    ) -> HashMap<KeyId, Curve25519PublicKey> {
+        let count = match policy {
+            EvictionPolicy::DropOldest => count,
+            EvictionPolicy::Refuse => {
+                count.min(Self::MAX_ONE_TIME_KEYS.saturating_sub(self.private_keys.len()))
+            }
+        };
+
+        let mut generated = HashMap::with_capacity(count);
+
         for _ in 0..count {
             let key_id = KeyId(self.next_key_id);
             let key = Curve25519SecretKey::new();
+            generated.insert(key_id, Curve25519PublicKey::from(&key));
 
             self.insert_secret_key(key_id, key, false);
 
             self.next_key_id = self.next_key_id.wrapping_add(1);
         }
+
+        generated
     }
 }
 
-#[derive(Serialize, Deserialize, Clone)]
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub(super) struct OneTimeKeysPickle {
-    #[serde(alias = "key_id")]
+    #[cfg_attr(feature = "serde", serde(alias = "key_id"))]
     next_key_id: u64,
     public_keys: BTreeMap<KeyId, Curve25519PublicKey>,
     private_keys: BTreeMap<KeyId, Curve25519SecretKey>,
@@ -139,7 +158,7 @@ impl From<OneTimeKeys> for OneTimeKeysPickle {
 #[cfg(test)]
 mod test {
     use super::OneTimeKeys;
-    use crate::types::KeyId;
+    use crate::{olm::account::EvictionPolicy, types::KeyId};
 
     #[test]
     fn store_limit() {
@@ -147,7 +166,7 @@ mod test {
 
         assert!(store.private_keys.is_empty());
 
-        store.generate(OneTimeKeys::MAX_ONE_TIME_KEYS);
+        store.generate(OneTimeKeys::MAX_ONE_TIME_KEYS, EvictionPolicy::DropOldest);
         assert_eq!(store.private_keys.len(), OneTimeKeys::MAX_ONE_TIME_KEYS);
         assert_eq!(store.unpublished_public_keys.len(), OneTimeKeys::MAX_ONE_TIME_KEYS);
         assert_eq!(store.key_ids_by_key.len(), OneTimeKeys::MAX_ONE_TIME_KEYS);
@@ -157,7 +176,7 @@ mod test {
         assert_eq!(store.private_keys.len(), OneTimeKeys::MAX_ONE_TIME_KEYS);
         assert_eq!(store.key_ids_by_key.len(), OneTimeKeys::MAX_ONE_TIME_KEYS);
 
-        store.generate(10);
+        store.generate(10, EvictionPolicy::DropOldest);
         assert_eq!(store.unpublished_public_keys.len(), 10);
         assert_eq!(store.private_keys.len(), OneTimeKeys::MAX_ONE_TIME_KEYS);
         assert_eq!(store.key_ids_by_key.len(), OneTimeKeys::MAX_ONE_TIME_KEYS);
@@ -167,4 +186,26 @@ mod test {
 
         assert_eq!(oldest_key_id, KeyId(10));
     }
+
+    #[test]
+    fn refusing_to_evict_keys() {
+        let mut store = OneTimeKeys::new();
+
+        assert_eq!(store.generate(10, EvictionPolicy::Refuse).len(), 10);
+        assert_eq!(
+            store.generate(OneTimeKeys::MAX_ONE_TIME_KEYS, EvictionPolicy::Refuse).len(),
+            OneTimeKeys::MAX_ONE_TIME_KEYS - 10
+        );
+        assert_eq!(store.private_keys.len(), OneTimeKeys::MAX_ONE_TIME_KEYS);
+
+        store.mark_as_published();
+        assert_eq!(store.generate(10, EvictionPolicy::Refuse).len(), 0);
+        assert!(store.unpublished_public_keys.is_empty());
+        assert_eq!(store.private_keys.keys().next().copied(), Some(KeyId(0)));
+
+        assert_eq!(store.generate(10, EvictionPolicy::DropOldest).len(), 10);
+        assert_eq!(store.private_keys.len(), OneTimeKeys::MAX_ONE_TIME_KEYS);
+        assert_eq!(store.key_ids_by_key.len(), OneTimeKeys::MAX_ONE_TIME_KEYS);
+        assert_eq!(store.private_keys.keys().next().copied(), Some(KeyId(10)));
+    }
 }
@@ -13,6 +13,7 @@
 // limitations under the License.
 
 use prost::Message as ProstMessage;
+#[cfg(feature = "serde")]
 use serde::{Deserialize, Serialize};
 
 use crate::{
@@ -165,6 +166,7 @@ impl Message {
     }
 }
 
+#[cfg(feature = "serde")]
 impl Serialize for Message {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
     where
@@ -175,6 +177,7 @@ impl Serialize for Message {
     }
 }
 
+#[cfg(feature = "serde")]
 impl<'de> Deserialize<'de> for Message {
     fn deserialize<D: serde::Deserializer<'de>>(d: D) -> Result<Self, D::Error> {
         let ciphertext = String::deserialize(d)?;
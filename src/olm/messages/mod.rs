@@ -17,6 +17,7 @@ mod pre_key;
 
 pub use message::Message;
 pub use pre_key::PreKeyMessage;
+#[cfg(feature = "serde")]
 use serde::{Deserialize, Serialize};
 
 use crate::DecodeError;
@@ -54,6 +55,7 @@ impl From<PreKeyMessage> for OlmMessage {
     }
 }
 
+#[cfg(feature = "serde")]
 #[derive(Serialize, Deserialize)]
 struct MessageSerdeHelper {
     #[serde(rename = "type")]
@@ -62,6 +64,7 @@ struct MessageSerdeHelper {
     ciphertext: String,
 }
 
+#[cfg(feature = "serde")]
 impl Serialize for OlmMessage {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
     where
@@ -75,6 +78,7 @@ impl Serialize for OlmMessage {
     }
 }
 
+#[cfg(feature = "serde")]
 impl<'de> Deserialize<'de> for OlmMessage {
     fn deserialize<D: serde::Deserializer<'de>>(d: D) -> Result<Self, D::Error> {
         let value = MessageSerdeHelper::deserialize(d)?;
@@ -177,6 +181,7 @@ impl From<OlmMessage> for LibolmMessage {
 mod tests {
     use anyhow::Result;
     use assert_matches::assert_matches;
+    #[cfg(feature = "serde")]
     use serde_json::json;
 
     use super::*;
@@ -211,6 +216,7 @@ mod tests {
     }
 
     #[test]
+    #[cfg(feature = "serde")]
     fn from_json() -> Result<()> {
         let value = json!({
             "type": 0u8,
@@ -13,6 +13,7 @@
 // limitations under the License.
 
 use prost::Message as ProstMessage;
+#[cfg(feature = "serde")]
 use serde::{Deserialize, Serialize};
 
 use super::Message;
@@ -160,6 +161,7 @@ impl PreKeyMessage {
     }
 }
 
+#[cfg(feature = "serde")]
 impl Serialize for PreKeyMessage {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
     where
@@ -170,6 +172,7 @@ impl Serialize for PreKeyMessage {
     }
 }
 
+#[cfg(feature = "serde")]
 impl<'de> Deserialize<'de> for PreKeyMessage {
     fn deserialize<D: serde::Deserializer<'de>>(d: D) -> Result<Self, D::Error> {
         let ciphertext = String::deserialize(d)?;
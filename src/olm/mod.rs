@@ -104,10 +104,15 @@ mod session_config;
 mod session_keys;
 mod shared_secret;
 
+#[cfg(feature = "serde")]
+pub use account::AccountPickle;
 pub use account::{
-    Account, AccountPickle, IdentityKeys, InboundCreationResult, SessionCreationError,
+    Account, EvictionPolicy, IdentityKeys, InboundCreationResult, OneTimeKeyCounts,
+    SessionCreationError,
 };
 pub use messages::{Message, MessageType, OlmMessage, PreKeyMessage};
-pub use session::{ratchet::RatchetPublicKey, DecryptionError, Session, SessionPickle};
+#[cfg(feature = "serde")]
+pub use session::SessionPickle;
+pub use session::{ratchet::RatchetPublicKey, DecryptionError, Session};
 pub use session_config::SessionConfig;
 pub use session_keys::SessionKeys;
@@ -13,6 +13,7 @@
 // limitations under the License.
 
 use hmac::{Hmac, Mac};
+#[cfg(feature = "serde")]
 use serde::{Deserialize, Serialize};
 use sha2::{digest::CtOutput, Sha256};
 use zeroize::Zeroize;
@@ -48,14 +49,16 @@ fn advance(key: &[u8; 32]) -> CtOutput<Hmac<Sha256>> {
     mac.finalize()
 }
 
-#[derive(Clone, Zeroize, Serialize, Deserialize)]
+#[derive(Clone, Zeroize)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 #[zeroize(drop)]
 pub(super) struct ChainKey {
     key: Box<[u8; 32]>,
     index: u64,
 }
 
-#[derive(Clone, Zeroize, Serialize, Deserialize)]
+#[derive(Clone, Zeroize)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 #[zeroize(drop)]
 pub(super) struct RemoteChainKey {
     key: Box<[u8; 32]>,
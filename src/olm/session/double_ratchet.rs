@@ -12,6 +12,7 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+#[cfg(feature = "serde")]
 use serde::{Deserialize, Serialize};
 
 use super::{
@@ -23,8 +24,9 @@ use super::{
 };
 use crate::olm::{messages::Message, shared_secret::Shared3DHSecret};
 
-#[derive(Serialize, Deserialize, Clone)]
-#[serde(transparent)]
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(transparent))]
 pub(super) struct DoubleRatchet {
     inner: DoubleRatchetState,
 }
@@ -106,9 +108,10 @@ impl DoubleRatchet {
     }
 }
 
-#[derive(Serialize, Deserialize, Clone)]
-#[serde(rename_all = "snake_case")]
-#[serde(tag = "type")]
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+#[cfg_attr(feature = "serde", serde(tag = "type"))]
 enum DoubleRatchetState {
     Inactive(InactiveDoubleRatchet),
     Active(ActiveDoubleRatchet),
@@ -126,7 +129,8 @@ impl From<ActiveDoubleRatchet> for DoubleRatchetState {
     }
 }
 
-#[derive(Serialize, Deserialize, Clone)]
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 struct InactiveDoubleRatchet {
     root_key: RemoteRootKey,
     ratchet_key: RemoteRatchetKey,
@@ -141,7 +145,8 @@ impl InactiveDoubleRatchet {
     }
 }
 
-#[derive(Serialize, Deserialize, Clone)]
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 struct ActiveDoubleRatchet {
     active_ratchet: Ratchet,
     symmetric_key_ratchet: ChainKey,
@@ -28,8 +28,10 @@ use hmac::digest::MacError;
 use ratchet::RemoteRatchetKey;
 use receiver_chain::ReceiverChain;
 use root_key::RemoteRootKey;
+#[cfg(feature = "serde")]
 use serde::{Deserialize, Serialize};
 use thiserror::Error;
+#[cfg(feature = "libolm-compat")]
 use zeroize::Zeroize;
 
 use super::{
@@ -42,14 +44,19 @@ use super::{
 use crate::hazmat::olm::MessageKey;
 use crate::{
     olm::messages::{Message, OlmMessage, PreKeyMessage},
+    Curve25519PublicKey,
+};
+#[cfg(feature = "serde")]
+use crate::{
     utilities::{pickle, unpickle},
-    Curve25519PublicKey, PickleError,
+    PickleError,
 };
 
 const MAX_RECEIVING_CHAINS: usize = 5;
 
 /// Error type for Olm-based decryption failuers.
 #[derive(Error, Debug)]
+#[non_exhaustive]
 pub enum DecryptionError {
     /// The message authentication code of the message was invalid.
     #[error("Failed decrypting Olm message, invalid MAC: {0}")]
@@ -71,7 +78,8 @@ pub enum DecryptionError {
     TooBigMessageGap(u64, u64),
 }
 
-#[derive(Serialize, Deserialize, Clone)]
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 struct ChainStore {
     inner: ArrayVec<ReceiverChain, MAX_RECEIVING_CHAINS>,
 }
@@ -292,6 +300,7 @@ impl Session {
 
     /// Convert the session into a struct which implements [`serde::Serialize`]
     /// and [`serde::Deserialize`].
+    #[cfg(feature = "serde")]
     pub fn pickle(&self) -> SessionPickle {
         SessionPickle {
             session_keys: self.session_keys,
@@ -302,6 +311,7 @@ impl Session {
     }
 
     /// Restore a [`Session`] from a previously saved [`SessionPickle`].
+    #[cfg(feature = "serde")]
     pub fn from_pickle(pickle: SessionPickle) -> Self {
         pickle.into()
     }
@@ -459,6 +469,7 @@ impl Session {
 
 /// A format suitable for serialization which implements [`serde::Serialize`]
 /// and [`serde::Deserialize`]. Obtainable by calling [`Session::pickle`].
+#[cfg(feature = "serde")]
 #[derive(Deserialize, Serialize)]
 pub struct SessionPickle {
     session_keys: SessionKeys,
@@ -468,10 +479,12 @@ pub struct SessionPickle {
     config: SessionConfig,
 }
 
+#[cfg(feature = "serde")]
 fn default_config() -> SessionConfig {
     SessionConfig::version_1()
 }
 
+#[cfg(feature = "serde")]
 impl SessionPickle {
     /// Serialize and encrypt the pickle using the given key.
     ///
@@ -489,6 +502,7 @@ impl SessionPickle {
     }
 }
 
+#[cfg(feature = "serde")]
 impl From<SessionPickle> for Session {
     fn from(pickle: SessionPickle) -> Self {
         Self {
@@ -509,11 +523,14 @@ mod test {
     };
 
     use super::Session;
+    #[cfg(feature = "serde")]
+    use crate::olm::SessionPickle;
     use crate::{
-        olm::{Account, SessionConfig, SessionPickle},
+        olm::{Account, SessionConfig},
         Curve25519PublicKey,
     };
 
+    #[cfg(feature = "serde")]
     const PICKLE_KEY: [u8; 32] = [0u8; 32];
 
     fn sessions() -> Result<(Account, OlmAccount, Session, OlmSession)> {
@@ -622,6 +639,7 @@ mod test {
     }
 
     #[test]
+    #[cfg(feature = "serde")]
     fn session_pickling_roundtrip_is_identity() -> Result<()> {
         let (_, _, session, _) = sessions()?;
 
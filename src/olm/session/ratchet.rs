@@ -13,6 +13,7 @@
 // limitations under the License.
 
 use matrix_pickle::Decode;
+#[cfg(feature = "serde")]
 use serde::{Deserialize, Serialize};
 use x25519_dalek::SharedSecret;
 
@@ -22,15 +23,17 @@ use super::{
 };
 use crate::{types::Curve25519SecretKey, Curve25519PublicKey};
 
-#[derive(Serialize, Deserialize, Clone)]
-#[serde(transparent)]
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(transparent))]
 pub(super) struct RatchetKey(Curve25519SecretKey);
 
 #[derive(Debug, PartialEq, Eq, Clone, Copy)]
 pub struct RatchetPublicKey(Curve25519PublicKey);
 
-#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq, Serialize, Deserialize, Decode)]
-#[serde(transparent)]
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq, Decode)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(transparent))]
 pub struct RemoteRatchetKey(Curve25519PublicKey);
 
 impl RatchetKey {
@@ -79,7 +82,8 @@ impl From<&RatchetKey> for RatchetPublicKey {
     }
 }
 
-#[derive(Serialize, Deserialize, Clone)]
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub(super) struct Ratchet {
     root_key: RootKey,
     ratchet_key: RatchetKey,
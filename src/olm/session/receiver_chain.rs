@@ -13,6 +13,7 @@
 // limitations under the License.
 
 use arrayvec::ArrayVec;
+#[cfg(feature = "serde")]
 use serde::{Deserialize, Serialize};
 
 use super::{
@@ -24,7 +25,8 @@ use crate::olm::{messages::Message, session_config::Version, SessionConfig};
 const MAX_MESSAGE_GAP: u64 = 2000;
 const MAX_MESSAGE_KEYS: usize = 40;
 
-#[derive(Serialize, Deserialize, Clone)]
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 struct MessageKeyStore {
     inner: ArrayVec<RemoteMessageKey, MAX_MESSAGE_KEYS>,
 }
@@ -86,7 +88,8 @@ impl FoundMessageKey<'_> {
     }
 }
 
-#[derive(Serialize, Deserialize, Clone)]
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub(super) struct ReceiverChain {
     ratchet_key: RemoteRatchetKey,
     hkdf_ratchet: RemoteChainKey,
@@ -13,6 +13,7 @@
 // limitations under the License.
 
 use hkdf::Hkdf;
+#[cfg(feature = "serde")]
 use serde::{Deserialize, Serialize};
 use sha2::Sha256;
 use zeroize::Zeroize;
@@ -24,14 +25,16 @@ use super::{
 
 const ADVANCEMENT_SEED: &[u8; 11] = b"OLM_RATCHET";
 
-#[derive(Serialize, Deserialize, Clone, Zeroize)]
-#[serde(transparent)]
+#[derive(Clone, Zeroize)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(transparent))]
 #[zeroize(drop)]
 pub(crate) struct RootKey {
     pub key: Box<[u8; 32]>,
 }
 
-#[derive(Serialize, Deserialize, Clone, Zeroize)]
+#[derive(Clone, Zeroize)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 #[zeroize(drop)]
 pub(crate) struct RemoteRootKey {
     pub key: Box<[u8; 32]>,
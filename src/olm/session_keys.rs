@@ -14,13 +14,15 @@
 // limitations under the License.
 
 use matrix_pickle::Decode;
+#[cfg(feature = "serde")]
 use serde::{Deserialize, Serialize};
 use sha2::{Digest, Sha256};
 
 use crate::{utilities::base64_encode, Curve25519PublicKey};
 
 /// The set of keys that were used to establish the Olm Session,
-#[derive(Clone, Copy, Deserialize, Serialize, PartialEq, Eq, Decode)]
+#[derive(Clone, Copy, PartialEq, Eq, Decode)]
+#[cfg_attr(feature = "serde", derive(Deserialize, Serialize))]
 pub struct SessionKeys {
     pub identity_key: Curve25519PublicKey,
     pub base_key: Curve25519PublicKey,
@@ -59,7 +59,7 @@ use thiserror::Error;
 use x25519_dalek::{EphemeralSecret, SharedSecret};
 
 use crate::{
-    utilities::{base64_decode, base64_encode},
+    utilities::{base64_decode, base64_encode, DecodeError},
     Curve25519PublicKey, KeyError,
 };
 
@@ -85,7 +85,7 @@ impl Mac {
     }
 
     /// Create a new `Mac` object from a base64 encoded string.
-    pub fn from_base64(mac: &str) -> Result<Self, base64::DecodeError> {
+    pub fn from_base64(mac: &str) -> Result<Self, DecodeError> {
         let bytes = base64_decode(mac)?;
 
         Ok(Self(bytes))
@@ -99,6 +99,7 @@ pub struct InvalidCount;
 
 /// Error type describing failures that can happen during the key verification.
 #[derive(Debug, Error)]
+#[non_exhaustive]
 pub enum SasError {
     /// The MAC failed to be validated.
     #[error("The SAS MAC validation didn't succeed: {0}")]
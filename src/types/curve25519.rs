@@ -16,15 +16,20 @@ use std::fmt::Display;
 
 use matrix_pickle::{Decode, DecodeError};
 use rand::thread_rng;
+#[cfg(feature = "serde")]
 use serde::{Deserialize, Serialize};
 use x25519_dalek::{EphemeralSecret, PublicKey, ReusableSecret, SharedSecret, StaticSecret};
 
 use super::KeyError;
-use crate::utilities::{base64_decode, base64_encode};
+use crate::utilities::{base64_decode, base64_display, base64_encode};
 
 /// Struct representing a Curve25519 secret key.
-#[derive(Clone, Deserialize, Serialize)]
-#[serde(transparent)]
+///
+/// **Note**: Like [`Curve25519SecretKey::new()`], [`Default`] generates a new
+/// random secret key.
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(Deserialize, Serialize))]
+#[cfg_attr(feature = "serde", serde(transparent))]
 pub struct Curve25519SecretKey(Box<StaticSecret>);
 
 impl Curve25519SecretKey {
@@ -58,14 +63,17 @@ impl Curve25519SecretKey {
 }
 
 impl Default for Curve25519SecretKey {
+    /// Generate a new random secret key, this is the same as calling
+    /// [`Curve25519SecretKey::new()`].
     fn default() -> Self {
         Self::new()
     }
 }
 
-#[derive(Serialize, Deserialize, Clone)]
-#[serde(from = "Curve25519KeypairPickle")]
-#[serde(into = "Curve25519KeypairPickle")]
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(from = "Curve25519KeypairPickle"))]
+#[cfg_attr(feature = "serde", serde(into = "Curve25519KeypairPickle"))]
 pub(crate) struct Curve25519Keypair {
     pub secret_key: Curve25519SecretKey,
     pub public_key: Curve25519PublicKey,
@@ -84,7 +92,7 @@ impl Curve25519Keypair {
         let secret_key = Curve25519SecretKey::from_slice(key);
         let public_key = Curve25519PublicKey::from(&secret_key);
 
-        Curve25519Keypair { secret_key, public_key }
+        Self { secret_key, public_key }
     }
 
     pub fn secret_key(&self) -> &Curve25519SecretKey {
@@ -94,11 +102,61 @@ impl Curve25519Keypair {
     pub fn public_key(&self) -> Curve25519PublicKey {
         self.public_key
     }
+
+    /// Create a pickle of this keypair, without consuming it.
+    #[cfg(feature = "serde")]
+    pub fn to_pickle(&self) -> Curve25519KeypairPickle {
+        Curve25519KeypairPickle(self.secret_key.clone())
+    }
 }
 
+/// The encodings of the points of small order on Curve25519, and its twist,
+/// with the most significant bit cleared.
+///
+/// This is the same list libsodium uses to reject public keys that would
+/// result in an all-zero, non-contributory, shared secret.
+const LOW_ORDER_POINTS: [[u8; Curve25519PublicKey::LENGTH]; 7] = [
+    [
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00,
+    ],
+    [
+        0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00,
+    ],
+    [
+        0xe0, 0xeb, 0x7a, 0x7c, 0x3b, 0x41, 0xb8, 0xae, 0x16, 0x56, 0xe3, 0xfa, 0xf1, 0x9f, 0xc4,
+        0x6a, 0xda, 0x09, 0x8d, 0xeb, 0x9c, 0x32, 0xb1, 0xfd, 0x86, 0x62, 0x05, 0x16, 0x5f, 0x49,
+        0xb8, 0x00,
+    ],
+    [
+        0x5f, 0x9c, 0x95, 0xbc, 0xa3, 0x50, 0x8c, 0x24, 0xb1, 0xd0, 0xb1, 0x55, 0x9c, 0x83, 0xef,
+        0x5b, 0x04, 0x44, 0x5c, 0xc4, 0x58, 0x1c, 0x8e, 0x86, 0xd8, 0x22, 0x4e, 0xdd, 0xd0, 0x9f,
+        0x11, 0x57,
+    ],
+    [
+        0xec, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff,
+        0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff,
+        0xff, 0x7f,
+    ],
+    [
+        0xed, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff,
+        0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff,
+        0xff, 0x7f,
+    ],
+    [
+        0xee, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff,
+        0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff,
+        0xff, 0x7f,
+    ],
+];
+
 /// Struct representing a Curve25519 public key.
-#[derive(PartialEq, Eq, Hash, Copy, Clone, Serialize, Deserialize)]
-#[serde(transparent)]
+#[derive(PartialEq, Eq, Hash, Copy, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(transparent))]
 pub struct Curve25519PublicKey {
     pub(crate) inner: PublicKey,
 }
@@ -158,15 +216,53 @@ impl Curve25519PublicKey {
         }
     }
 
+    /// Try to create a `Curve25519PublicKey` from a slice of bytes, rejecting
+    /// keys of small order.
+    ///
+    /// A Diffie-Hellman key exchange with a key of small order results in a
+    /// shared secret that is known to everybody, see
+    /// [`Curve25519PublicKey::is_low_order()`].
+    pub fn from_slice_contributory(slice: &[u8]) -> Result<Curve25519PublicKey, KeyError> {
+        let key = Self::from_slice(slice)?;
+
+        if key.is_low_order() {
+            Err(KeyError::NonContributoryKey)
+        } else {
+            Ok(key)
+        }
+    }
+
+    /// Check if this public key is one of the known points of small order.
+    ///
+    /// The shared secret of a Diffie-Hellman key exchange with such a key
+    /// doesn't depend on our secret key, it does not have contributory
+    /// behaviour. The most significant bit of the key is ignored, like it is
+    /// ignored by the X25519 function.
+    pub fn is_low_order(&self) -> bool {
+        let mut bytes = self.to_bytes();
+        bytes[Self::LENGTH - 1] &= 0x7f;
+
+        LOW_ORDER_POINTS.contains(&bytes)
+    }
+
     /// Serialize a Curve25519 public key to an unpadded base64 representation.
     pub fn to_base64(&self) -> String {
         base64_encode(self.inner.as_bytes())
     }
+
+    /// Create a random public key, e.g. for test data.
+    ///
+    /// The key is derived from a throwaway secret key which is zeroized before
+    /// this method returns, the secret key is never exposed.
+    #[cfg(feature = "testing")]
+    pub fn random(rng: &mut (impl rand::CryptoRng + rand::RngCore)) -> Self {
+        Self::from(&EphemeralSecret::new(rng))
+    }
 }
 
 impl Display for Curve25519PublicKey {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}", self.to_base64())
+        write!(f, "{}", base64_display(self.as_bytes()))
     }
 }
 
@@ -177,6 +273,18 @@ impl std::fmt::Debug for Curve25519PublicKey {
     }
 }
 
+impl PartialEq<[u8; Curve25519PublicKey::LENGTH]> for Curve25519PublicKey {
+    fn eq(&self, other: &[u8; Curve25519PublicKey::LENGTH]) -> bool {
+        self.as_bytes() == other
+    }
+}
+
+impl PartialEq<Curve25519PublicKey> for [u8; Curve25519PublicKey::LENGTH] {
+    fn eq(&self, other: &Curve25519PublicKey) -> bool {
+        self == other.as_bytes()
+    }
+}
+
 impl From<[u8; Self::LENGTH]> for Curve25519PublicKey {
     fn from(bytes: [u8; Self::LENGTH]) -> Curve25519PublicKey {
         Curve25519PublicKey { inner: PublicKey::from(bytes) }
@@ -201,10 +309,57 @@ impl<'a> From<&'a ReusableSecret> for Curve25519PublicKey {
     }
 }
 
+/// A [`Curve25519PublicKey`] which is serialized as an unpadded base64
+/// string.
+///
+/// This is the form Curve25519 keys take in Matrix device keys. The serde
+/// implementation of [`Curve25519PublicKey`] itself is used for pickles and
+/// doesn't produce, nor accept, strings.
+///
+/// Deserialization fails if the string isn't valid base64 or doesn't decode
+/// to exactly [`Curve25519PublicKey::LENGTH`] bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(try_from = "String", into = "String"))]
+pub struct DeviceCurve25519Key(pub Curve25519PublicKey);
+
+impl TryFrom<String> for DeviceCurve25519Key {
+    type Error = KeyError;
+
+    fn try_from(key: String) -> Result<Self, Self::Error> {
+        Ok(Self(Curve25519PublicKey::from_base64(&key)?))
+    }
+}
+
+impl From<DeviceCurve25519Key> for String {
+    fn from(key: DeviceCurve25519Key) -> Self {
+        key.0.to_base64()
+    }
+}
+
+impl From<Curve25519PublicKey> for DeviceCurve25519Key {
+    fn from(key: Curve25519PublicKey) -> Self {
+        Self(key)
+    }
+}
+
+impl From<DeviceCurve25519Key> for Curve25519PublicKey {
+    fn from(key: DeviceCurve25519Key) -> Self {
+        key.0
+    }
+}
+
+/// The pickled form of a [`Curve25519Keypair`].
+///
+/// Only the secret key is stored, the public key is always recomputed from it
+/// when unpickling. This way a pickle can't contain a public key which doesn't
+/// belong to the secret key.
+#[cfg(feature = "serde")]
 #[derive(Serialize, Deserialize)]
 #[serde(transparent)]
 pub(crate) struct Curve25519KeypairPickle(Curve25519SecretKey);
 
+#[cfg(feature = "serde")]
 impl From<Curve25519KeypairPickle> for Curve25519Keypair {
     fn from(pickle: Curve25519KeypairPickle) -> Self {
         let secret_key = pickle.0;
@@ -214,6 +369,7 @@ impl From<Curve25519KeypairPickle> for Curve25519Keypair {
     }
 }
 
+#[cfg(feature = "serde")]
 impl From<Curve25519Keypair> for Curve25519KeypairPickle {
     fn from(key: Curve25519Keypair) -> Self {
         Curve25519KeypairPickle(key.secret_key)
@@ -222,9 +378,69 @@ impl From<Curve25519Keypair> for Curve25519KeypairPickle {
 
 #[cfg(test)]
 mod tests {
-    use super::Curve25519PublicKey;
+    use rand::thread_rng;
+    use x25519_dalek::{EphemeralSecret, PublicKey, ReusableSecret, StaticSecret};
+
+    #[cfg(feature = "serde")]
+    use super::DeviceCurve25519Key;
+    use super::{Curve25519Keypair, Curve25519PublicKey, Curve25519SecretKey, LOW_ORDER_POINTS};
+    #[cfg(feature = "serde")]
+    use crate::{fixture, utilities::unpickle};
     use crate::{utilities::DecodeError, KeyError};
 
+    #[test]
+    fn default_generates_a_random_secret_key() {
+        let new = Curve25519PublicKey::from(&Curve25519SecretKey::new());
+        let default = Curve25519PublicKey::from(&Curve25519SecretKey::default());
+
+        assert_ne!(new, default);
+        assert_ne!(default, Curve25519PublicKey::from(&Curve25519SecretKey::default()));
+    }
+
+    #[test]
+    #[cfg(feature = "serde")]
+    fn pickle_only_contains_the_secret_key() {
+        let keypair = Curve25519Keypair::new();
+        let pickle =
+            serde_json::to_value(&keypair).expect("We should be able to serialize a keypair");
+
+        // The pickle is just the secret key, there's no room for a public key
+        // that could mismatch it. The public key is always derived again.
+        let secret_key: Vec<u8> =
+            serde_json::from_value(pickle.clone()).expect("The pickle should be a byte array");
+        assert_eq!(secret_key, keypair.secret_key().to_bytes());
+
+        let unpickled: Curve25519Keypair =
+            serde_json::from_value(pickle).expect("We should be able to deserialize a pickle");
+        assert_eq!(unpickled.public_key(), keypair.public_key());
+        assert_eq!(unpickled.public_key(), Curve25519PublicKey::from(unpickled.secret_key()));
+    }
+
+    #[test]
+    fn display_matches_base64() {
+        let public_key = Curve25519Keypair::new().public_key();
+        assert_eq!(public_key.to_string(), public_key.to_base64());
+    }
+
+    #[test]
+    #[cfg(feature = "serde")]
+    fn to_pickle_roundtrip() {
+        let keypair = Curve25519Keypair::new();
+        let unpickled = Curve25519Keypair::from(keypair.to_pickle());
+
+        assert_eq!(unpickled.public_key(), keypair.public_key());
+        assert_eq!(unpickled.secret_key().to_bytes(), keypair.secret_key().to_bytes());
+    }
+
+    #[test]
+    #[cfg(feature = "serde")]
+    fn unpickling_stored_fixture() {
+        let keypair: Curve25519Keypair = unpickle(&fixture("curve25519_keypair.pickle"), &[0; 32])
+            .expect("We should be able to unpickle the stored Curve25519 keypair");
+
+        assert_eq!(keypair.public_key().to_base64(), "cVqUrF95uVHFcQXYmHBWHawdFsSZ4JWYCAr2WKT2sSc");
+    }
+
     #[test]
     fn decoding_invalid_base64_fails() {
         let base64_payload = "a";
@@ -255,9 +471,127 @@ mod tests {
         ));
     }
 
+    #[test]
+    fn low_order_points_are_rejected() {
+        let secret_key = Curve25519SecretKey::new();
+
+        for point in LOW_ORDER_POINTS {
+            let mut high_bit_set = point;
+            high_bit_set[Curve25519PublicKey::LENGTH - 1] |= 0x80;
+
+            for encoding in [point, high_bit_set] {
+                let key = Curve25519PublicKey::from_bytes(encoding);
+
+                assert!(key.is_low_order(), "{key} should be a low order point");
+                assert_eq!(secret_key.diffie_hellman(&key).as_bytes(), &[0u8; 32]);
+                assert!(matches!(
+                    Curve25519PublicKey::from_slice_contributory(&encoding),
+                    Err(KeyError::NonContributoryKey)
+                ));
+            }
+        }
+    }
+
+    #[test]
+    fn random_keys_are_contributory() {
+        let key = Curve25519PublicKey::from(&Curve25519SecretKey::new());
+
+        assert!(!key.is_low_order());
+        assert_eq!(
+            Curve25519PublicKey::from_slice_contributory(key.as_bytes())
+                .expect("A random key should be contributory"),
+            key
+        );
+    }
+
     #[test]
     fn decoding_of_correct_num_of_bytes_succeeds() {
         let base64_payload = "MDAwMDAwMDAwMDAwMDAwMDAwMDAwMDAwMDAwMDAwMDA";
         assert!(matches!(Curve25519PublicKey::from_base64(base64_payload), Ok(..)));
     }
+
+    #[test]
+    fn public_key_conversions_agree() {
+        let secret_bytes = [7u8; 32];
+
+        let secret_key = Curve25519SecretKey::from_slice(&secret_bytes);
+        let static_secret = StaticSecret::from(secret_bytes);
+        let dalek_public_key = PublicKey::from(&static_secret);
+
+        let from_secret = Curve25519PublicKey::from(&secret_key);
+        let from_bytes = Curve25519PublicKey::from_bytes(dalek_public_key.to_bytes());
+        let from_array = Curve25519PublicKey::from(dalek_public_key.to_bytes());
+        let from_slice = Curve25519PublicKey::from_slice(dalek_public_key.as_bytes())
+            .expect("We should be able to decode a public key from a slice");
+
+        assert_eq!(from_secret, from_bytes);
+        assert_eq!(from_secret, from_array);
+        assert_eq!(from_secret, from_slice);
+        assert_eq!(from_secret, *dalek_public_key.as_bytes());
+        assert_eq!(from_secret.to_vec(), from_secret.to_bytes());
+
+        let from_base64 = Curve25519PublicKey::from_base64(&from_secret.to_base64())
+            .expect("We should be able to decode our own base64 encoded public key");
+        assert_eq!(from_secret, from_base64);
+
+        let ephemeral_secret = EphemeralSecret::new(thread_rng());
+        assert_eq!(
+            Curve25519PublicKey::from(&ephemeral_secret).to_bytes(),
+            PublicKey::from(&ephemeral_secret).to_bytes()
+        );
+
+        let reusable_secret = ReusableSecret::new(thread_rng());
+        assert_eq!(
+            Curve25519PublicKey::from(&reusable_secret).to_bytes(),
+            PublicKey::from(&reusable_secret).to_bytes()
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "serde")]
+    fn device_key_serializes_as_base64() {
+        let key = Curve25519Keypair::new().public_key;
+        let json = serde_json::to_value(DeviceCurve25519Key(key))
+            .expect("We should be able to serialize a device key");
+
+        assert_eq!(json, key.to_base64());
+
+        let decoded: DeviceCurve25519Key =
+            serde_json::from_value(json).expect("We should be able to deserialize a device key");
+        assert_eq!(Curve25519PublicKey::from(decoded), key);
+    }
+
+    #[test]
+    #[cfg(feature = "serde")]
+    fn device_key_with_invalid_length_fails_to_deserialize() {
+        let error = serde_json::from_str::<DeviceCurve25519Key>(r#""AAAA""#)
+            .expect_err("A too short device key should not deserialize");
+
+        assert!(error.to_string().contains("expected 32, got 3"), "{error}");
+    }
+
+    #[test]
+    #[cfg(feature = "testing")]
+    fn random_public_keys() {
+        let mut rng = thread_rng();
+
+        let first = Curve25519PublicKey::random(&mut rng);
+        let second = Curve25519PublicKey::random(&mut rng);
+
+        assert_ne!(first, second);
+        assert!(!first.is_low_order());
+        assert_eq!(Curve25519PublicKey::from_base64(&first.to_base64()).ok(), Some(first));
+    }
+
+    #[test]
+    fn public_key_equals_its_bytes() {
+        let key = Curve25519PublicKey::from(&Curve25519SecretKey::new());
+        let bytes = key.to_bytes();
+        let other = Curve25519PublicKey::from(&Curve25519SecretKey::new()).to_bytes();
+
+        assert_eq!(key, bytes);
+        assert_eq!(bytes, key);
+        assert_ne!(key, other);
+        assert_ne!(other, key);
+    }
 }
@@ -12,42 +12,64 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use std::fmt::Display;
+use std::{fmt::Display, sync::Arc};
 
+use curve25519_dalek::edwards::CompressedEdwardsY;
 #[cfg(not(fuzzing))]
 use ed25519_dalek::Verifier;
 use ed25519_dalek::{
-    ExpandedSecretKey, Keypair, PublicKey, SecretKey, Signature, PUBLIC_KEY_LENGTH,
-    SIGNATURE_LENGTH,
+    ExpandedSecretKey, Keypair, PublicKey, SecretKey, Signature, KEYPAIR_LENGTH, PUBLIC_KEY_LENGTH,
+    SECRET_KEY_LENGTH, SIGNATURE_LENGTH,
 };
 use rand::thread_rng;
+#[cfg(feature = "serde")]
 use serde::{Deserialize, Serialize};
+use sha2_09::{Digest, Sha512};
+use subtle::{Choice, ConstantTimeEq};
 use thiserror::Error;
 use zeroize::Zeroize;
 
-use crate::utilities::{base64_decode, base64_encode};
+use super::{Curve25519PublicKey, Curve25519SecretKey};
+use crate::utilities::{base64_decode, base64_display, base64_encode, DecodeError};
 
 /// Error type describing signature verification failures.
 #[derive(Debug, Error)]
+#[non_exhaustive]
 pub enum SignatureError {
     /// The signature wasn't valid base64.
-    #[error("The signature couldn't be decoded: {0}")]
-    Base64(#[from] base64::DecodeError),
+    #[error("The signature couldn't be decoded")]
+    Base64(#[from] DecodeError),
     /// The signature failed to be verified.
-    #[error("The signature was invalid: {0}")]
+    #[error("The signature was invalid")]
     Signature(#[from] ed25519_dalek::SignatureError),
+    /// The signature didn't have the expected number of bytes.
+    #[error("The signature had an invalid length, expected {expected}, got {got}")]
+    InvalidLength {
+        /// The number of bytes an Ed25519 signature has.
+        expected: usize,
+        /// The number of bytes that were given.
+        got: usize,
+    },
 }
 
 /// A struct collecting both a public, and a secret, Ed25519 key.
-#[derive(Deserialize, Serialize)]
-#[serde(try_from = "Ed25519KeypairPickle")]
-#[serde(into = "Ed25519KeypairPickle")]
+///
+/// **Note**: [`Default`] is implemented as [`Ed25519Keypair::new()`], every
+/// call generates a new random keypair. It doesn't produce an empty or a
+/// well-known key.
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(Deserialize, Serialize))]
+#[cfg_attr(feature = "serde", serde(try_from = "Ed25519KeypairPickle"))]
+#[cfg_attr(feature = "serde", serde(into = "Ed25519KeypairPickle"))]
 pub struct Ed25519Keypair {
     secret_key: SecretKeys,
     public_key: Ed25519PublicKey,
 }
 
 impl Ed25519Keypair {
+    /// The number of bytes a serialized `Ed25519Keypair` has.
+    pub const LENGTH: usize = KEYPAIR_LENGTH;
+
     /// Create a new, random, `Ed25519Keypair`.
     pub fn new() -> Self {
         let mut rng = thread_rng();
@@ -64,6 +86,52 @@ impl Ed25519Keypair {
         Ok(Self { secret_key: secret_key.into(), public_key })
     }
 
+    /// Convert the keypair to a byte array.
+    ///
+    /// The layout is the same one ed25519-dalek's `Keypair::to_bytes()` uses,
+    /// the 32 byte secret key is followed by the 32 byte public key.
+    ///
+    /// Returns `None` if the keypair was restored from a libolm pickle. libolm
+    /// only stores the expanded form of the secret key, which can't be
+    /// converted back into the 32 byte secret key.
+    ///
+    /// **Warning**: The array should be zeroized after it has been used,
+    /// otherwise an unintentional copy of the secret key might exist in
+    /// memory.
+    pub fn to_bytes(&self) -> Option<[u8; Self::LENGTH]> {
+        match &self.secret_key {
+            SecretKeys::Normal(secret_key) => {
+                let mut bytes = [0u8; Self::LENGTH];
+
+                bytes[..SECRET_KEY_LENGTH].copy_from_slice(secret_key.as_bytes());
+                bytes[SECRET_KEY_LENGTH..].copy_from_slice(self.public_key.as_bytes());
+
+                Some(bytes)
+            }
+            SecretKeys::Expanded(_) => None,
+        }
+    }
+
+    /// Restore a keypair from the byte array produced by
+    /// [`Ed25519Keypair::to_bytes()`].
+    ///
+    /// Fails with [`KeyError::MismatchedKeypair`] if the public key isn't the
+    /// one that belongs to the secret key.
+    ///
+    /// [`KeyError::MismatchedKeypair`]: crate::KeyError::MismatchedKeypair
+    pub fn from_bytes(bytes: &[u8; Self::LENGTH]) -> Result<Self, crate::KeyError> {
+        let secret_key: SecretKeys = SecretKey::from_bytes(&bytes[..SECRET_KEY_LENGTH])
+            .map_err(SignatureError::from)?
+            .into();
+        let public_key = secret_key.public_key();
+
+        if public_key.as_bytes() == &bytes[SECRET_KEY_LENGTH..] {
+            Ok(Self { secret_key, public_key })
+        } else {
+            Err(crate::KeyError::MismatchedKeypair)
+        }
+    }
+
     /// Get the public Ed25519 key of this keypair.
     pub fn public_key(&self) -> Ed25519PublicKey {
         self.public_key
@@ -73,17 +141,40 @@ impl Ed25519Keypair {
     pub fn sign(&self, message: &[u8]) -> Ed25519Signature {
         self.secret_key.sign(message, &self.public_key())
     }
+
+    /// Sign a prehashed message using Ed25519ph, as defined in [RFC8032].
+    ///
+    /// The optional context, at most 255 bytes long, is bound to the
+    /// signature, it has to be given again when the signature is verified.
+    ///
+    /// **Note**: Ed25519ph is a different signature scheme than the one
+    /// [`Ed25519Keypair::sign()`] uses. A prehashed signature can only be
+    /// verified using [`Ed25519PublicKey::verify_prehashed()`] and vice versa.
+    ///
+    /// [RFC8032]: https://datatracker.ietf.org/doc/html/rfc8032#section-5.1
+    pub fn sign_prehashed(
+        &self,
+        prehash: Ed25519Prehash,
+        context: Option<&[u8]>,
+    ) -> Result<Ed25519Signature, SignatureError> {
+        self.secret_key.sign_prehashed(prehash, context, &self.public_key)
+    }
 }
 
 impl Default for Ed25519Keypair {
+    /// Generate a new random keypair, this is the same as calling
+    /// [`Ed25519Keypair::new()`].
     fn default() -> Self {
         Self::new()
     }
 }
 
 /// An Ed25519 secret key, used to create digital signatures.
-#[derive(Deserialize, Serialize)]
-#[serde(transparent)]
+///
+/// **Note**: Like [`Ed25519SecretKey::new()`], [`Default`] generates a new
+/// random secret key.
+#[cfg_attr(feature = "serde", derive(Deserialize, Serialize))]
+#[cfg_attr(feature = "serde", serde(transparent))]
 pub struct Ed25519SecretKey(Box<SecretKey>);
 
 impl Ed25519SecretKey {
@@ -119,6 +210,10 @@ impl Ed25519SecretKey {
     }
 
     /// Try to create a `Ed25519SecretKey` from a base64 encoded string.
+    ///
+    /// The decoded bytes are zeroized before this method returns. The
+    /// `from_base64()` methods of public keys and signatures don't do this,
+    /// since they don't handle secret material.
     pub fn from_base64(key: &str) -> Result<Self, crate::KeyError> {
         let mut bytes = base64_decode(key)?;
         let key = Self::from_slice(&bytes);
@@ -133,6 +228,31 @@ impl Ed25519SecretKey {
         Ed25519PublicKey(PublicKey::from(self.0.as_ref()))
     }
 
+    /// Convert this secret key to a [`Curve25519SecretKey`].
+    ///
+    /// The Curve25519 secret key is the clamped scalar Ed25519 derives from
+    /// the secret key, i.e. the first half of its SHA-512 hash. This is the
+    /// same conversion libsodium's `crypto_sign_ed25519_sk_to_curve25519()`
+    /// performs. The public part of the result matches
+    /// [`Ed25519PublicKey::to_curve25519()`] of
+    /// [`Ed25519SecretKey::public_key()`].
+    ///
+    /// **Warning**: The converted key is only meant for key agreement. Using
+    /// the same secret for Ed25519 signatures and X25519 key agreement is only
+    /// safe if the protocol was designed for it.
+    pub fn to_curve25519_secret(&self) -> Curve25519SecretKey {
+        let mut hash = Sha512::digest(self.as_bytes());
+        let mut scalar = [0u8; 32];
+        scalar.copy_from_slice(&hash[..32]);
+
+        let secret_key = Curve25519SecretKey::from_slice(&scalar);
+
+        scalar.zeroize();
+        hash.as_mut_slice().zeroize();
+
+        secret_key
+    }
+
     /// Sign the given slice of bytes with this `Ed25519SecretKey`.
     ///
     /// The signature can be verified using the public key.
@@ -158,15 +278,23 @@ impl Ed25519SecretKey {
 }
 
 impl Default for Ed25519SecretKey {
+    /// Generate a new random secret key, this is the same as calling
+    /// [`Ed25519SecretKey::new()`].
     fn default() -> Self {
         Self::new()
     }
 }
 
-#[derive(Serialize, Deserialize)]
+/// The secret half of an [`Ed25519Keypair`].
+///
+/// The dalek secret key types don't implement `Clone`, so clones of a keypair
+/// share the secret key instead of copying it. The key is zeroized once the
+/// last keypair using it is dropped.
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 enum SecretKeys {
-    Normal(Box<SecretKey>),
-    Expanded(Box<ExpandedSecretKey>),
+    Normal(Arc<SecretKey>),
+    Expanded(Arc<ExpandedSecretKey>),
 }
 
 impl SecretKeys {
@@ -177,6 +305,23 @@ impl SecretKeys {
         }
     }
 
+    fn sign_prehashed(
+        &self,
+        prehash: Ed25519Prehash,
+        context: Option<&[u8]>,
+        public_key: &Ed25519PublicKey,
+    ) -> Result<Ed25519Signature, SignatureError> {
+        let signature = match &self {
+            SecretKeys::Normal(k) => {
+                let expanded = ExpandedSecretKey::from(k.as_ref());
+                expanded.sign_prehashed(prehash.0, &public_key.0, context)
+            }
+            SecretKeys::Expanded(k) => k.sign_prehashed(prehash.0, &public_key.0, context),
+        }?;
+
+        Ok(Ed25519Signature(signature))
+    }
+
     fn sign(&self, message: &[u8], public_key: &Ed25519PublicKey) -> Ed25519Signature {
         let signature = match &self {
             SecretKeys::Normal(k) => {
@@ -191,10 +336,103 @@ impl SecretKeys {
 }
 
 /// An Ed25519 public key, used to verify digital signatures.
-#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
-#[serde(transparent)]
+///
+/// Human-readable formats, e.g. JSON, serialize the key as an unpadded base64
+/// string, the form Ed25519 keys take in the Matrix protocol. Binary formats
+/// use the raw 32 bytes of the key. Deserializing from a human-readable format
+/// additionally accepts the array of bytes older versions of vodozemac
+/// produced. Pickles keep storing the key in that byte form.
+#[derive(Clone, Copy, PartialEq, Eq)]
 pub struct Ed25519PublicKey(PublicKey);
 
+/// Serde helpers which serialize an [`Ed25519PublicKey`] as bytes, even for
+/// human-readable formats.
+///
+/// Used with `#[serde(with = ...)]` for pickle fields, so the pickle format
+/// stays the same as before the key got its base64 form.
+#[cfg(feature = "serde")]
+pub(crate) mod ed25519_public_key_bytes {
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    use super::Ed25519PublicKey;
+
+    pub(crate) fn serialize<S: Serializer>(
+        key: &Ed25519PublicKey,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.serialize_bytes(key.as_bytes())
+    }
+
+    pub(crate) fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Ed25519PublicKey, D::Error> {
+        Ed25519PublicKey::deserialize(deserializer)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl Serialize for Ed25519PublicKey {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        if serializer.is_human_readable() {
+            serializer.serialize_str(&self.to_base64())
+        } else {
+            serializer.serialize_bytes(self.as_bytes())
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> Deserialize<'de> for Ed25519PublicKey {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        struct KeyVisitor;
+
+        impl<'de> serde::de::Visitor<'de> for KeyVisitor {
+            type Value = Ed25519PublicKey;
+
+            fn expecting(&self, formatter: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                write!(
+                    formatter,
+                    "an unpadded base64 string or {} bytes of an Ed25519 public key",
+                    Ed25519PublicKey::LENGTH
+                )
+            }
+
+            fn visit_str<E: serde::de::Error>(self, v: &str) -> Result<Self::Value, E> {
+                Ed25519PublicKey::from_base64(v).map_err(E::custom)
+            }
+
+            fn visit_bytes<E: serde::de::Error>(self, v: &[u8]) -> Result<Self::Value, E> {
+                Ed25519PublicKey::from_slice(v).map_err(E::custom)
+            }
+
+            fn visit_seq<A>(self, mut seq: A) -> Result<Self::Value, A::Error>
+            where
+                A: serde::de::SeqAccess<'de>,
+            {
+                let mut bytes = Vec::with_capacity(Ed25519PublicKey::LENGTH);
+
+                while let Some(byte) = seq.next_element()? {
+                    bytes.push(byte);
+                }
+
+                self.visit_bytes(&bytes)
+            }
+        }
+
+        if deserializer.is_human_readable() {
+            deserializer.deserialize_any(KeyVisitor)
+        } else {
+            deserializer.deserialize_bytes(KeyVisitor)
+        }
+    }
+}
+
 impl Ed25519PublicKey {
     /// The number of bytes a Ed25519 public key has.
     pub const LENGTH: usize = PUBLIC_KEY_LENGTH;
@@ -209,6 +447,11 @@ impl Ed25519PublicKey {
         self.0.as_bytes()
     }
 
+    /// Convert this public key to a byte array.
+    pub fn to_bytes(&self) -> [u8; Self::LENGTH] {
+        self.0.to_bytes()
+    }
+
     /// Instantiate a Ed25519PublicKey public key from an unpadded base64
     /// representation.
     pub fn from_base64(base64_key: &str) -> Result<Self, crate::KeyError> {
@@ -218,25 +461,59 @@ impl Ed25519PublicKey {
 
     /// Serialize a Ed25519PublicKey public key to an unpadded base64
     /// representation.
+    ///
+    /// This allocates a new `String` on every call. The `Display`
+    /// implementation produces the same output without allocating, prefer it
+    /// when the key is only being formatted, e.g. for logging.
     pub fn to_base64(&self) -> String {
         base64_encode(self.as_bytes())
     }
 
+    /// Convert this public key to a [`Curve25519PublicKey`] using the
+    /// birational map between the Edwards and the Montgomery form of
+    /// Curve25519.
+    ///
+    /// The Montgomery form only contains the u-coordinate of a point, the
+    /// sign of the Edwards x-coordinate is lost. Two different Ed25519 keys
+    /// can therefore map to the same Curve25519 key, the conversion can't be
+    /// reversed without knowing the sign bit.
+    ///
+    /// **Warning**: The converted key is only meant for key agreement, see
+    /// [`Ed25519SecretKey::to_curve25519_secret()`].
+    pub fn to_curve25519(&self) -> Curve25519PublicKey {
+        let point = CompressedEdwardsY(self.to_bytes())
+            .decompress()
+            .expect("An Ed25519 public key should always be a valid point");
+
+        Curve25519PublicKey::from(point.to_montgomery().to_bytes())
+    }
+
+    /// Get the [`ed25519_dalek`] verifying key this public key wraps, for
+    /// operations vodozemac doesn't expose.
+    ///
+    /// **Warning**: This is an escape hatch, the returned type belongs to
+    /// ed25519-dalek and will change whenever vodozemac upgrades it. With the
+    /// current 1.x series of ed25519-dalek, the verifying key is called
+    /// [`ed25519_dalek::PublicKey`]. No semver guarantees are made for this
+    /// method.
+    #[cfg(feature = "dalek-internals")]
+    pub fn as_verifying_key(&self) -> &PublicKey {
+        &self.0
+    }
+
     /// Verify that the provided signature for a given message has been signed
     /// by the private key matching this public one.
     ///
-    /// By default this performs an [RFC8032] compatible signature check. A
-    /// stricter version of the signature check can be enabled with the
-    /// `strict-signatures` feature flag.
+    /// By default this performs an [RFC8032] compatible signature check, see
+    /// [`Ed25519PublicKey::verify_lenient()`]. A stricter version of the
+    /// signature check, [`Ed25519PublicKey::verify_strict()`], can be enabled
+    /// with the `strict-signatures` feature flag.
     ///
-    /// The stricter variant is compatible with libsodium 0.16 and under the
-    /// hood uses the [`ed25519_dalek::PublicKey::verify_strict()`] method.
-    ///
-    /// For more info, see the ed25519_dalek [README] and [this] post.
+    /// The feature flag applies to the whole crate, including the signature
+    /// checks vodozemac does internally. Call the explicit methods to pick the
+    /// variant on a per key basis.
     ///
     /// [RFC8032]: https://datatracker.ietf.org/doc/html/rfc8032#section-5.1.7
-    /// [README]: https://github.com/dalek-cryptography/ed25519-dalek#a-note-on-signature-malleability
-    /// [this]: https://hdevalence.ca/blog/2020-10-04-its-25519am
     #[cfg(not(fuzzing))]
     pub fn verify(
         &self,
@@ -244,9 +521,9 @@ impl Ed25519PublicKey {
         signature: &Ed25519Signature,
     ) -> Result<(), SignatureError> {
         if cfg!(feature = "strict-signatures") {
-            Ok(self.0.verify_strict(message, &signature.0)?)
+            self.verify_strict(message, signature)
         } else {
-            Ok(self.0.verify(message, &signature.0)?)
+            self.verify_lenient(message, signature)
         }
     }
 
@@ -258,11 +535,69 @@ impl Ed25519PublicKey {
     ) -> Result<(), SignatureError> {
         Ok(())
     }
+
+    /// Verify a signature using the strict signature check, regardless of the
+    /// `strict-signatures` feature flag.
+    ///
+    /// The strict check is compatible with libsodium 0.16 and under the hood
+    /// uses the [`ed25519_dalek::PublicKey::verify_strict()`] method. It
+    /// rejects weak public keys and non-canonical signatures, which makes the
+    /// signatures non-malleable.
+    ///
+    /// For more info, see the ed25519_dalek [README] and [this] post.
+    ///
+    /// [README]: https://github.com/dalek-cryptography/ed25519-dalek#a-note-on-signature-malleability
+    /// [this]: https://hdevalence.ca/blog/2020-10-04-its-25519am
+    pub fn verify_strict(
+        &self,
+        message: &[u8],
+        signature: &Ed25519Signature,
+    ) -> Result<(), SignatureError> {
+        Ok(self.0.verify_strict(message, &signature.0)?)
+    }
+
+    /// Verify a signature using the [RFC8032] compatible signature check,
+    /// regardless of the `strict-signatures` feature flag.
+    ///
+    /// [RFC8032]: https://datatracker.ietf.org/doc/html/rfc8032#section-5.1.7
+    pub fn verify_lenient(
+        &self,
+        message: &[u8],
+        signature: &Ed25519Signature,
+    ) -> Result<(), SignatureError> {
+        Ok(self.0.verify(message, &signature.0)?)
+    }
+
+    /// Verify an Ed25519ph signature of a prehashed message, created using
+    /// [`Ed25519Keypair::sign_prehashed()`].
+    ///
+    /// The context has to match the one the signature was created with.
+    /// Signatures created using [`Ed25519Keypair::sign()`] never pass this
+    /// check.
+    pub fn verify_prehashed(
+        &self,
+        prehash: Ed25519Prehash,
+        context: Option<&[u8]>,
+        signature: &Ed25519Signature,
+    ) -> Result<(), SignatureError> {
+        Ok(self.0.verify_prehashed(prehash.0, context, &signature.0)?)
+    }
+
+    /// Verify that the provided base64 encoded signature for a given message
+    /// has been signed by the private key matching this public one.
+    ///
+    /// This is a shorthand for decoding the signature using
+    /// [`Ed25519Signature::from_base64()`] and then calling
+    /// [`Ed25519PublicKey::verify()`].
+    pub fn verify_base64(&self, message: &[u8], signature: &str) -> Result<(), SignatureError> {
+        let signature = Ed25519Signature::from_base64(signature)?;
+        self.verify(message, &signature)
+    }
 }
 
 impl Display for Ed25519PublicKey {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}", self.to_base64())
+        write!(f, "{}", base64_display(self.as_bytes()))
     }
 }
 
@@ -273,8 +608,44 @@ impl std::fmt::Debug for Ed25519PublicKey {
     }
 }
 
+impl PartialEq<[u8; Ed25519PublicKey::LENGTH]> for Ed25519PublicKey {
+    fn eq(&self, other: &[u8; Ed25519PublicKey::LENGTH]) -> bool {
+        self.as_bytes() == other
+    }
+}
+
+impl PartialEq<Ed25519PublicKey> for [u8; Ed25519PublicKey::LENGTH] {
+    fn eq(&self, other: &Ed25519PublicKey) -> bool {
+        self == other.as_bytes()
+    }
+}
+
+/// The SHA-512 hash of a message that should be signed, or verified, using
+/// Ed25519ph.
+///
+/// The message can be fed in chunks, which allows large messages to be signed
+/// without keeping them in memory.
+#[derive(Clone, Default)]
+pub struct Ed25519Prehash(Sha512);
+
+impl Ed25519Prehash {
+    /// Create a new, empty, `Ed25519Prehash`.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Feed the next chunk of the message into the hash.
+    pub fn update(&mut self, data: impl AsRef<[u8]>) {
+        self.0.update(data);
+    }
+}
+
 /// An Ed25519 digital signature, can be used to verify the authenticity of a
 /// message.
+///
+/// **Note**: Comparing signatures using `==` is not done in constant time. Use
+/// the [`ConstantTimeEq`] implementation when comparing a signature against
+/// an expected value.
 #[derive(Clone, Copy, PartialEq, Eq)]
 pub struct Ed25519Signature(pub(crate) Signature);
 
@@ -284,13 +655,17 @@ impl Ed25519Signature {
 
     /// Try to create a `Ed25519Signature` from a slice of bytes.
     pub fn from_slice(bytes: &[u8]) -> Result<Self, SignatureError> {
-        Ok(Self(Signature::try_from(bytes)?))
+        if bytes.len() != Self::LENGTH {
+            Err(SignatureError::InvalidLength { expected: Self::LENGTH, got: bytes.len() })
+        } else {
+            Ok(Self(Signature::try_from(bytes)?))
+        }
     }
 
     /// Try to create a `Ed25519Signature` from an unpadded base64
     /// representation.
     pub fn from_base64(signature: &str) -> Result<Self, SignatureError> {
-        Ok(Self(Signature::try_from(base64_decode(signature)?.as_slice())?))
+        Self::from_slice(&base64_decode(signature)?)
     }
 
     /// Serialize an `Ed25519Signature` to an unpadded base64 representation.
@@ -302,11 +677,52 @@ impl Ed25519Signature {
     pub fn to_bytes(&self) -> [u8; Self::LENGTH] {
         self.0.to_bytes()
     }
+
+    /// Verify that this signature for the given message has been created by
+    /// the private key matching the given public key.
+    ///
+    /// This is the same check as [`Ed25519PublicKey::verify()`], with the
+    /// arguments flipped around.
+    pub fn verify(&self, message: &[u8], key: &Ed25519PublicKey) -> Result<(), SignatureError> {
+        key.verify(message, self)
+    }
+}
+
+impl From<Ed25519Signature> for [u8; Ed25519Signature::LENGTH] {
+    fn from(signature: Ed25519Signature) -> Self {
+        signature.to_bytes()
+    }
+}
+
+/// Converting bytes into an `Ed25519Signature` doesn't check if the signature
+/// is valid, this only happens when the signature is verified.
+///
+/// The conversion isn't infallible, arrays whose `s` scalar has any of its
+/// three most significant bits set can't be a valid signature and are
+/// rejected.
+impl TryFrom<[u8; Ed25519Signature::LENGTH]> for Ed25519Signature {
+    type Error = SignatureError;
+
+    fn try_from(bytes: [u8; Ed25519Signature::LENGTH]) -> Result<Self, Self::Error> {
+        Self::from_slice(&bytes)
+    }
+}
+
+impl ConstantTimeEq for Ed25519Signature {
+    fn ct_eq(&self, other: &Self) -> Choice {
+        self.to_bytes().ct_eq(&other.to_bytes())
+    }
+}
+
+impl std::hash::Hash for Ed25519Signature {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.to_bytes().hash(state);
+    }
 }
 
 impl Display for Ed25519Signature {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}", self.to_base64())
+        write!(f, "{}", base64_display(&self.to_bytes()))
     }
 }
 
@@ -317,26 +733,7 @@ impl std::fmt::Debug for Ed25519Signature {
     }
 }
 
-impl Clone for Ed25519Keypair {
-    fn clone(&self) -> Self {
-        let secret_key: Result<SecretKeys, _> = match &self.secret_key {
-            SecretKeys::Normal(k) => SecretKey::from_bytes(k.as_bytes()).map(|k| k.into()),
-            SecretKeys::Expanded(k) => {
-                let mut bytes = k.to_bytes();
-                let key = ExpandedSecretKey::from_bytes(&bytes).map(|k| k.into());
-                bytes.zeroize();
-
-                key
-            }
-        };
-
-        Self {
-            secret_key: secret_key.expect("Couldn't create a secret key copy."),
-            public_key: self.public_key,
-        }
-    }
-}
-
+#[cfg(feature = "serde")]
 impl From<Ed25519Keypair> for Ed25519KeypairPickle {
     fn from(key: Ed25519Keypair) -> Self {
         Self(key.secret_key)
@@ -345,20 +742,22 @@ impl From<Ed25519Keypair> for Ed25519KeypairPickle {
 
 impl From<SecretKey> for SecretKeys {
     fn from(key: SecretKey) -> Self {
-        Self::Normal(Box::new(key))
+        Self::Normal(Arc::new(key))
     }
 }
 
 impl From<ExpandedSecretKey> for SecretKeys {
     fn from(key: ExpandedSecretKey) -> Self {
-        Self::Expanded(Box::new(key))
+        Self::Expanded(Arc::new(key))
     }
 }
 
+#[cfg(feature = "serde")]
 #[derive(Serialize, Deserialize)]
 #[serde(transparent)]
 pub struct Ed25519KeypairPickle(SecretKeys);
 
+#[cfg(feature = "serde")]
 impl From<Ed25519KeypairPickle> for Ed25519Keypair {
     fn from(pickle: Ed25519KeypairPickle) -> Self {
         let secret_key = pickle.0;
@@ -367,3 +766,435 @@ impl From<Ed25519KeypairPickle> for Ed25519Keypair {
         Self { secret_key, public_key }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use std::{collections::HashSet, error::Error};
+
+    use assert_matches::assert_matches;
+    use subtle::ConstantTimeEq;
+
+    use super::{
+        Curve25519PublicKey, Ed25519Keypair, Ed25519Prehash, Ed25519PublicKey, Ed25519SecretKey,
+        Ed25519Signature, SignatureError,
+    };
+    #[cfg(feature = "serde")]
+    use crate::{fixture, utilities::unpickle};
+    use crate::{
+        utilities::{base64_encode, DecodeError},
+        KeyError,
+    };
+
+    #[test]
+    fn default_generates_random_keys() {
+        let new = Ed25519Keypair::new();
+        let default = Ed25519Keypair::default();
+
+        assert_ne!(new.public_key(), default.public_key());
+        assert_ne!(default.public_key(), Ed25519Keypair::default().public_key());
+        assert_ne!(Ed25519SecretKey::default().public_key(), Ed25519SecretKey::new().public_key());
+    }
+
+    #[test]
+    #[cfg(feature = "serde")]
+    fn unpickling_stored_fixture() {
+        let keypair: Ed25519Keypair = unpickle(&fixture("ed25519_keypair.pickle"), &[0; 32])
+            .expect("We should be able to unpickle the stored Ed25519 keypair");
+
+        assert_eq!(keypair.public_key().to_base64(), "8KvOAQfNGCIBBhtVy2C+qx7Gm7MzLu9cmywdAXl0dZA");
+    }
+
+    #[test]
+    fn conversion_to_curve25519_matches_libsodium() {
+        // Test vector from libsodium's ed25519_convert test.
+        let seed = [
+            0x42, 0x11, 0x51, 0xa4, 0x59, 0xfa, 0xea, 0xde, 0x3d, 0x24, 0x71, 0x15, 0xf9, 0x4a,
+            0xed, 0xae, 0x42, 0x31, 0x81, 0x24, 0x09, 0x5a, 0xfa, 0xbe, 0x4d, 0x14, 0x51, 0xa5,
+            0x59, 0xfa, 0xed, 0xee,
+        ];
+        let secret_key = Ed25519SecretKey::from_slice(&seed).expect("The seed is a valid key");
+
+        let curve_secret = secret_key.to_curve25519_secret();
+        let curve_public = secret_key.public_key().to_curve25519();
+
+        assert_eq!(
+            curve_public.as_bytes(),
+            &[
+                0xf1, 0x81, 0x4f, 0x0e, 0x8f, 0xf1, 0x04, 0x3d, 0x8a, 0x44, 0xd2, 0x5b, 0xab, 0xff,
+                0x3c, 0xed, 0xca, 0xe6, 0xc2, 0x2c, 0x3e, 0xda, 0xa4, 0x8f, 0x85, 0x7a, 0xe7, 0x0d,
+                0xe2, 0xba, 0xae, 0x50,
+            ]
+        );
+        assert_eq!(
+            curve_secret.to_bytes(),
+            [
+                0x80, 0x52, 0x03, 0x03, 0x76, 0xd4, 0x71, 0x12, 0xbe, 0x7f, 0x73, 0xed, 0x7a, 0x01,
+                0x92, 0x93, 0xdd, 0x12, 0xad, 0x91, 0x0b, 0x65, 0x44, 0x55, 0x79, 0x8b, 0x46, 0x67,
+                0xd7, 0x3d, 0xe1, 0x66,
+            ]
+        );
+    }
+
+    #[test]
+    fn converted_keys_match() {
+        for _ in 0..16 {
+            let secret_key = Ed25519SecretKey::new();
+            let curve_secret = secret_key.to_curve25519_secret();
+
+            assert_eq!(
+                Curve25519PublicKey::from(&curve_secret),
+                secret_key.public_key().to_curve25519()
+            );
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "dalek-internals")]
+    fn verifying_key_is_the_wrapped_dalek_key() {
+        use ed25519_dalek::Verifier;
+
+        let keypair = Ed25519Keypair::new();
+        let public_key = keypair.public_key();
+        let signature = keypair.sign(b"It's dangerous to go alone");
+        let verifying_key = public_key.as_verifying_key();
+
+        assert_eq!(verifying_key.as_bytes(), public_key.as_bytes());
+        verifying_key
+            .verify(b"It's dangerous to go alone", &signature.0)
+            .expect("The dalek key should verify our signatures");
+    }
+
+    #[test]
+    fn display_matches_base64() {
+        let keypair = Ed25519Keypair::new();
+        let public_key = keypair.public_key();
+        let signature = keypair.sign(b"It's dangerous to go alone");
+
+        assert_eq!(public_key.to_string(), public_key.to_base64());
+        assert_eq!(signature.to_string(), signature.to_base64());
+    }
+
+    #[test]
+    fn keypair_bytes_roundtrip() {
+        let keypair = Ed25519Keypair::new();
+        let mut bytes = keypair.to_bytes().expect("A new keypair should be serializable");
+
+        assert_eq!(&bytes[32..], keypair.public_key().as_bytes());
+
+        let restored = Ed25519Keypair::from_bytes(&bytes)
+            .expect("We should be able to restore the keypair from its bytes");
+        assert_eq!(restored.public_key(), keypair.public_key());
+
+        let signature = restored.sign(b"It's dangerous to go alone");
+        keypair.public_key().verify(b"It's dangerous to go alone", &signature).expect(
+            "The restored keypair should create signatures the original public key can verify",
+        );
+
+        bytes[32..].copy_from_slice(Ed25519Keypair::new().public_key().as_bytes());
+        assert!(matches!(Ed25519Keypair::from_bytes(&bytes), Err(KeyError::MismatchedKeypair)));
+    }
+
+    #[test]
+    #[cfg(feature = "libolm-compat")]
+    fn expanded_keypair_has_no_byte_representation() {
+        let keypair = Ed25519Keypair::from_expanded_key(&[1; 64])
+            .expect("We should be able to create a keypair from an expanded key");
+
+        assert!(keypair.to_bytes().is_none());
+    }
+
+    #[test]
+    fn public_key_to_bytes() {
+        let public_key = Ed25519Keypair::new().public_key();
+        let bytes = public_key.to_bytes();
+
+        assert_eq!(&bytes, public_key.as_bytes());
+        assert_eq!(
+            Ed25519PublicKey::from_slice(&bytes)
+                .expect("We should be able to decode our own public key"),
+            public_key
+        );
+    }
+
+    #[test]
+    fn strict_and_lenient_verification() {
+        let keypair = Ed25519Keypair::new();
+        let message = b"It's dangerous to go alone";
+        let signature = keypair.sign(message);
+        let public_key = keypair.public_key();
+
+        public_key.verify_strict(message, &signature).expect("The signature has to be valid");
+        public_key.verify_lenient(message, &signature).expect("The signature has to be valid");
+
+        // The identity point is a weak public key, a signature of it with an
+        // identity R and a zero s passes the lenient check but not the strict
+        // one.
+        let mut identity = [0u8; 32];
+        identity[0] = 1;
+        let weak_key = Ed25519PublicKey::from_slice(&identity)
+            .expect("The identity point should be a decodable public key");
+        let mut weak_signature = [0u8; 64];
+        weak_signature[0] = 1;
+        let weak_signature = Ed25519Signature::from_slice(&weak_signature)
+            .expect("The forged signature should be decodable");
+
+        weak_key
+            .verify_lenient(message, &weak_signature)
+            .expect("The lenient check should accept a signature of a weak key");
+        assert_matches!(
+            weak_key.verify_strict(message, &weak_signature),
+            Err(SignatureError::Signature(_))
+        );
+    }
+
+    #[test]
+    fn prehashed_signatures() {
+        let keypair = Ed25519Keypair::new();
+        let public_key = keypair.public_key();
+
+        let mut prehash = Ed25519Prehash::new();
+        prehash.update(b"It's dangerous ");
+        prehash.update(b"to go alone");
+
+        let signature = keypair
+            .sign_prehashed(prehash.clone(), Some(b"attachment"))
+            .expect("We should be able to sign a prehashed message");
+
+        public_key
+            .verify_prehashed(prehash.clone(), Some(b"attachment"), &signature)
+            .expect("The prehashed signature should be valid");
+        assert!(public_key.verify_prehashed(prehash.clone(), None, &signature).is_err());
+        assert!(public_key.verify(b"It's dangerous to go alone", &signature).is_err());
+
+        let plain_signature = keypair.sign(b"It's dangerous to go alone");
+        assert!(public_key.verify_prehashed(prehash.clone(), None, &plain_signature).is_err());
+
+        assert!(keypair.sign_prehashed(prehash, Some(&[0; 256])).is_err());
+    }
+
+    #[test]
+    fn signatures_verify_against_a_public_key() {
+        let keypair = Ed25519Keypair::new();
+        let message = b"It's dangerous to go alone";
+        let signature = keypair.sign(message);
+
+        signature.verify(message, &keypair.public_key()).expect("The signature has to be valid");
+
+        assert_matches!(
+            signature.verify(b"Take this", &keypair.public_key()),
+            Err(SignatureError::Signature(_))
+        );
+        assert_matches!(
+            signature.verify(message, &Ed25519Keypair::new().public_key()),
+            Err(SignatureError::Signature(_))
+        );
+    }
+
+    #[test]
+    fn cloned_keypairs_sign_identically() {
+        let keypair = Ed25519Keypair::new();
+        let cloned = keypair.clone();
+        let message = b"It's dangerous to go alone";
+
+        assert_eq!(cloned.public_key(), keypair.public_key());
+        assert_eq!(cloned.public_key().to_base64(), keypair.public_key().to_base64());
+        assert_eq!(cloned.sign(message), keypair.sign(message));
+    }
+
+    #[test]
+    #[cfg(feature = "serde")]
+    fn cloned_keypairs_pickle_identically() {
+        let keypair = Ed25519Keypair::new();
+        let cloned = keypair.clone();
+
+        let pickle = serde_json::to_string(&keypair).expect("The keypair should serialize");
+        let cloned_pickle = serde_json::to_string(&cloned).expect("The clone should serialize");
+        assert_eq!(pickle, cloned_pickle);
+
+        drop(keypair);
+        let message = b"It's dangerous to go alone";
+        cloned.public_key().verify(message, &cloned.sign(message)).expect("The clone should sign");
+    }
+
+    #[test]
+    #[cfg(feature = "libolm-compat")]
+    fn cloned_expanded_keypairs_sign_identically() {
+        let keypair = Ed25519Keypair::from_expanded_key(&[7; 64])
+            .expect("A 64 byte array should be a valid expanded key");
+        let cloned = keypair.clone();
+        let message = b"It's dangerous to go alone";
+
+        assert_eq!(cloned.public_key(), keypair.public_key());
+        assert_eq!(cloned.sign(message), keypair.sign(message));
+    }
+
+    #[test]
+    fn verifying_base64_signatures() {
+        let keypair = Ed25519Keypair::new();
+        let message = b"It's dangerous to go alone";
+        let signature = keypair.sign(message).to_base64();
+
+        keypair
+            .public_key()
+            .verify_base64(message, &signature)
+            .expect("The signature has to be valid");
+
+        assert_matches!(
+            keypair.public_key().verify_base64(b"Take this", &signature),
+            Err(SignatureError::Signature(_))
+        );
+        assert_matches!(
+            keypair.public_key().verify_base64(message, "not base64!"),
+            Err(SignatureError::Base64(_))
+        );
+    }
+
+    #[test]
+    fn signature_constant_time_equality() {
+        let keypair = Ed25519Keypair::new();
+        let signature = keypair.sign(b"It's dangerous to go alone");
+        let other = keypair.sign(b"Take this");
+
+        let copy = Ed25519Signature::from_slice(&signature.to_bytes())
+            .expect("We should be able to decode our own signature");
+
+        assert!(bool::from(signature.ct_eq(&copy)));
+        assert!(!bool::from(signature.ct_eq(&other)));
+    }
+
+    #[test]
+    fn signatures_can_be_deduplicated() {
+        let keypair = Ed25519Keypair::new();
+        let signature = keypair.sign(b"It's dangerous to go alone");
+        let other = keypair.sign(b"Take this");
+
+        let signatures: HashSet<_> = [signature, other, signature].into_iter().collect();
+
+        assert_eq!(signatures.len(), 2);
+        assert!(signatures.contains(&signature));
+        assert!(signatures.contains(&other));
+    }
+
+    #[test]
+    fn signature_length_is_checked() {
+        let signature = Ed25519Keypair::new().sign(b"It's dangerous to go alone").to_bytes();
+
+        assert_matches!(
+            Ed25519Signature::from_slice(&signature[..Ed25519Signature::LENGTH - 1]),
+            Err(SignatureError::InvalidLength { expected: 64, got: 63 })
+        );
+        assert_matches!(
+            Ed25519Signature::from_slice(&[signature.as_ref(), &[0]].concat()),
+            Err(SignatureError::InvalidLength { expected: 64, got: 65 })
+        );
+        assert_matches!(
+            Ed25519Signature::from_base64(""),
+            Err(SignatureError::InvalidLength { expected: 64, got: 0 })
+        );
+
+        let mut malformed = signature;
+        malformed[63] = 0xff;
+
+        assert_matches!(
+            Ed25519Signature::from_slice(&malformed),
+            Err(SignatureError::Signature(_))
+        );
+    }
+
+    #[test]
+    fn base64_errors_are_kept_apart_from_invalid_keys() {
+        assert_matches!(
+            Ed25519PublicKey::from_base64("not base64!"),
+            Err(KeyError::Base64Error(DecodeError::InvalidByte(..)))
+        );
+
+        let too_short = base64_encode([0u8; Ed25519PublicKey::LENGTH - 1]);
+        assert_matches!(Ed25519PublicKey::from_base64(&too_short), Err(KeyError::Signature(_)));
+    }
+
+    #[test]
+    fn error_source_chain() {
+        let error = Ed25519PublicKey::from_base64("not base64!")
+            .expect_err("Decoding an invalid base64 string should fail");
+        assert_matches!(error, KeyError::Base64Error(_));
+
+        let source = error.source().expect("The base64 error should be the source");
+        assert!(source.downcast_ref::<DecodeError>().is_some());
+
+        let error = Ed25519PublicKey::from_slice(&[2u8; Ed25519PublicKey::LENGTH])
+            .expect_err("Decoding an invalid Ed25519 point should fail");
+        assert_matches!(error, KeyError::Signature(_));
+
+        let source = error.source().expect("The signature error should be the source");
+        assert!(source.downcast_ref::<SignatureError>().is_some());
+
+        let source = source.source().expect("The dalek error should be the source");
+        assert!(source.downcast_ref::<ed25519_dalek::SignatureError>().is_some());
+    }
+
+    #[test]
+    fn public_key_equals_its_bytes() {
+        let key = Ed25519Keypair::new().public_key();
+        let bytes = key.to_bytes();
+        let other = Ed25519Keypair::new().public_key().to_bytes();
+
+        assert_eq!(key, bytes);
+        assert_eq!(bytes, key);
+        assert_ne!(key, other);
+        assert_ne!(other, key);
+    }
+
+    #[test]
+    #[cfg(feature = "serde")]
+    fn public_key_serializes_to_base64_in_json() {
+        let key = Ed25519Keypair::new().public_key();
+
+        let json = serde_json::to_value(key).expect("The key should serialize");
+        assert_eq!(json, serde_json::json!(key.to_base64()));
+
+        let deserialized: Ed25519PublicKey =
+            serde_json::from_value(json).expect("The key should deserialize");
+        assert_eq!(deserialized, key);
+
+        let legacy = serde_json::json!(key.as_bytes().to_vec());
+        let deserialized: Ed25519PublicKey =
+            serde_json::from_value(legacy).expect("The legacy byte array should deserialize");
+        assert_eq!(deserialized, key);
+    }
+
+    #[test]
+    #[cfg(feature = "serde")]
+    fn invalid_public_keys_fail_to_deserialize() {
+        let too_short = serde_json::json!("AAAA");
+        serde_json::from_value::<Ed25519PublicKey>(too_short)
+            .expect_err("A too short key should not deserialize");
+
+        let too_short = serde_json::json!([0u8; 31].to_vec());
+        serde_json::from_value::<Ed25519PublicKey>(too_short)
+            .expect_err("A too short byte array should not deserialize");
+
+        let not_a_point = serde_json::json!(base64_encode([2u8; 32]));
+        serde_json::from_value::<Ed25519PublicKey>(not_a_point)
+            .expect_err("Bytes that aren't a valid point should not deserialize");
+
+        let not_base64 = serde_json::json!("$$$$");
+        serde_json::from_value::<Ed25519PublicKey>(not_base64)
+            .expect_err("A key that isn't base64 should not deserialize");
+    }
+
+    #[test]
+    fn signature_byte_array_roundtrip() {
+        let signature = Ed25519Keypair::new().sign(b"It's dangerous to go alone");
+
+        let bytes: [u8; Ed25519Signature::LENGTH] = signature.into();
+        assert_eq!(bytes, signature.to_bytes());
+
+        let converted =
+            Ed25519Signature::try_from(bytes).expect("A valid signature should convert back");
+        assert_eq!(converted, signature);
+
+        let mut unreduced = bytes;
+        unreduced[Ed25519Signature::LENGTH - 1] |= 0b1110_0000;
+        assert_matches!(Ed25519Signature::try_from(unreduced), Err(SignatureError::Signature(_)));
+    }
+}
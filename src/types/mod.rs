@@ -15,16 +15,24 @@
 mod curve25519;
 mod ed25519;
 
-pub use curve25519::Curve25519PublicKey;
-pub(crate) use curve25519::{Curve25519Keypair, Curve25519KeypairPickle, Curve25519SecretKey};
+pub(crate) use curve25519::Curve25519Keypair;
+#[cfg(feature = "serde")]
+pub(crate) use curve25519::Curve25519KeypairPickle;
+pub use curve25519::{Curve25519PublicKey, Curve25519SecretKey, DeviceCurve25519Key};
+#[cfg(feature = "serde")]
+pub(crate) use ed25519::ed25519_public_key_bytes;
+#[cfg(feature = "serde")]
+pub use ed25519::Ed25519KeypairPickle;
 pub use ed25519::{
-    Ed25519Keypair, Ed25519KeypairPickle, Ed25519PublicKey, Ed25519SecretKey, Ed25519Signature,
+    Ed25519Keypair, Ed25519Prehash, Ed25519PublicKey, Ed25519SecretKey, Ed25519Signature,
     SignatureError,
 };
+#[cfg(feature = "serde")]
 use serde::{Deserialize, Serialize};
 use thiserror::Error;
 
-#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct KeyId(pub(super) u64);
 
 impl From<KeyId> for String {
@@ -42,17 +50,26 @@ impl KeyId {
 /// Error type describing failures that can happen when we try decode or use a
 /// cryptographic key.
 #[derive(Error, Debug)]
+#[non_exhaustive]
 pub enum KeyError {
-    #[error("Failed decoding a public key from base64: {}", .0)]
-    Base64Error(#[from] base64::DecodeError),
+    /// The key wasn't valid base64, the decoded bytes were never looked at.
+    #[error("Failed decoding a public key from base64")]
+    Base64Error(#[from] crate::utilities::DecodeError),
+    /// The key was valid base64, but didn't decode into the number of bytes a
+    /// Curve25519 key has.
     #[error("Failed decoding curve25519 key from base64: \
              Invalid number of bytes for curve25519, expected {}, got {}.",
             Curve25519PublicKey::LENGTH, .0)]
     InvalidKeyLength(usize),
-    #[error(transparent)]
+    /// The bytes of the key weren't a valid Ed25519 key, this includes
+    /// Ed25519 keys with an invalid length.
+    #[error("Failed decoding an Ed25519 key")]
     Signature(#[from] SignatureError),
     /// At least one of the keys did not have contributory behaviour and the
     /// resulting shared secret would have been insecure.
     #[error("At least one of the keys did not have contributory behaviour")]
     NonContributoryKey,
+    /// The public key of an encoded keypair doesn't belong to its secret key.
+    #[error("The public key of the keypair doesn't match its secret key")]
+    MismatchedKeypair,
 }
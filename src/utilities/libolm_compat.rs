@@ -14,10 +14,10 @@
 
 use std::io::Cursor;
 
-use matrix_pickle::Decode;
+use matrix_pickle::{Decode, Encode};
 use zeroize::Zeroize;
 
-use super::base64_decode;
+use super::{base64_decode, base64_encode};
 use crate::{cipher::Cipher, LibolmPickleError};
 
 /// Decrypt and decode the given pickle with the given pickle key.
@@ -52,17 +52,35 @@ pub(crate) fn unpickle_libolm<P: Decode, T: TryFrom<P, Error = LibolmPickleError
     // A pickle starts with a version, which will decide how we need to decode.
     // We only support the latest version so bail out if it isn't the expected
     // pickle version.
-    let version = get_version(&decrypted).ok_or(LibolmPickleError::MissingVersion)?;
+    let pickle = match get_version(&decrypted) {
+        Some(version) if version == pickle_version => {
+            let mut cursor = Cursor::new(&decrypted);
+            P::decode(&mut cursor).map_err(LibolmPickleError::from)
+        }
+        Some(version) => Err(LibolmPickleError::Version(pickle_version, version)),
+        None => Err(LibolmPickleError::MissingVersion),
+    };
 
-    if version == pickle_version {
-        let mut cursor = Cursor::new(&decrypted);
-        let pickle = P::decode(&mut cursor)?;
+    // Make sure the decrypted pickle doesn't linger in memory, no matter if we
+    // managed to decode it or not.
+    decrypted.zeroize();
 
-        decrypted.zeroize();
-        pickle.try_into()
-    } else {
-        Err(LibolmPickleError::Version(pickle_version, version))
-    }
+    pickle?.try_into()
+}
+
+/// Encode and encrypt the given pickle with the given pickle key, producing a
+/// pickle that libolm can read.
+///
+/// The pickle is expected to start with its version, as libolm expects it.
+pub(crate) fn pickle_libolm<P: Encode>(pickle: &P, pickle_key: &[u8]) -> String {
+    let mut encoded = pickle.encode_to_vec().expect("Can't encode a libolm pickle");
+
+    let cipher = Cipher::new_pickle(pickle_key);
+    let ciphertext = cipher.encrypt_pickle(&encoded);
+
+    encoded.zeroize();
+
+    base64_encode(ciphertext)
 }
 
 #[derive(Zeroize, Decode)]
@@ -18,18 +18,69 @@ mod libolm_compat;
 
 pub use base64::DecodeError;
 #[cfg(feature = "libolm-compat")]
-pub(crate) use libolm_compat::{unpickle_libolm, LibolmEd25519Keypair};
+pub(crate) use libolm_compat::{pickle_libolm, unpickle_libolm, LibolmEd25519Keypair};
+
+/// The base64 configuration used for every encoding and decoding operation.
+///
+/// This is the standard alphabet without padding. Trailing bits, i.e. set bits
+/// in the last symbol that don't belong to any decoded byte, are rejected, so
+/// every byte sequence has exactly one valid encoding.
+const BASE64_CONFIG: base64::Config = base64::STANDARD_NO_PAD.decode_allow_trailing_bits(false);
 
 /// Decode the input as base64 with no padding.
+///
+/// Padded input is rejected, the `base64` crate would otherwise silently
+/// accept the padding characters.
 pub fn base64_decode(input: impl AsRef<[u8]>) -> Result<Vec<u8>, DecodeError> {
-    base64::decode_config(input, base64::STANDARD_NO_PAD)
+    let input = input.as_ref();
+
+    if let Some(position) = input.iter().position(|&byte| byte == b'=') {
+        Err(DecodeError::InvalidByte(position, b'='))
+    } else {
+        base64::decode_config(input, BASE64_CONFIG)
+    }
 }
 
 /// Encode the input as base64 with no padding.
 pub fn base64_encode(input: impl AsRef<[u8]>) -> String {
-    base64::encode_config(input, base64::STANDARD_NO_PAD)
+    let input = input.as_ref();
+    let mut encoded = String::with_capacity(unpadded_base64_len(input.len()));
+
+    base64::encode_config_buf(input, BASE64_CONFIG, &mut encoded);
+
+    encoded
+}
+
+/// Compare two base64 encoded strings in constant time.
+///
+/// Both inputs are decoded before the comparison, so two encodings are equal
+/// only if they decode to the same bytes. Returns `false` if either of the
+/// inputs isn't valid unpadded base64.
+///
+/// The comparison doesn't leak at which position the decoded bytes differ,
+/// the length of the inputs isn't considered to be secret.
+pub fn ct_eq_base64(a: &str, b: &str) -> bool {
+    use subtle::ConstantTimeEq;
+
+    match (base64_decode(a), base64_decode(b)) {
+        (Ok(a), Ok(b)) => a.ct_eq(&b).into(),
+        _ => false,
+    }
+}
+
+/// Format the input as base64 with no padding, without allocating an
+/// intermediate `String`.
+pub(crate) fn base64_display(input: &[u8]) -> base64::display::Base64Display<'_> {
+    base64::display::Base64Display::with_config(input, BASE64_CONFIG)
+}
+
+/// Get the length of the unpadded base64 encoding of an input with the given
+/// length.
+pub const fn unpadded_base64_len(input_len: usize) -> usize {
+    (input_len * 4 + 2) / 3
 }
 
+#[cfg(feature = "serde")]
 pub(crate) fn unpickle<T: for<'b> serde::Deserialize<'b>>(
     ciphertext: &str,
     pickle_key: &[u8; 32],
@@ -40,13 +91,14 @@ pub(crate) fn unpickle<T: for<'b> serde::Deserialize<'b>>(
     let decoded = base64_decode(ciphertext)?;
     let mut plaintext = cipher.decrypt_pickle(&decoded)?;
 
-    let pickle = serde_json::from_slice(&plaintext)?;
+    let pickle = serde_json::from_slice(&plaintext);
 
     plaintext.zeroize();
 
-    Ok(pickle)
+    Ok(pickle?)
 }
 
+#[cfg(feature = "serde")]
 pub(crate) fn pickle<T: serde::Serialize>(thing: &T, pickle_key: &[u8; 32]) -> String {
     use zeroize::Zeroize;
 
@@ -142,3 +194,73 @@ impl VarInt for u64 {
         v
     }
 }
+
+#[cfg(test)]
+mod test {
+    use proptest::prelude::*;
+
+    use super::{base64_decode, base64_encode, ct_eq_base64, unpadded_base64_len, DecodeError};
+
+    #[test]
+    fn unpadded_base64_len_matches_the_encoding() {
+        for len in 0..=64 {
+            let input = vec![0xffu8; len];
+            let encoded = base64_encode(&input);
+
+            assert_eq!(unpadded_base64_len(len), encoded.len(), "Input length {len}");
+            assert_eq!(encoded.capacity(), encoded.len(), "Input length {len}");
+        }
+    }
+
+    #[test]
+    fn padded_input_is_rejected() {
+        assert_eq!(base64_decode("AA").expect("Unpadded input should decode"), [0]);
+        assert_eq!(base64_decode("AA=="), Err(DecodeError::InvalidByte(2, b'=')));
+        assert_eq!(base64_decode("AAA="), Err(DecodeError::InvalidByte(3, b'=')));
+    }
+
+    #[test]
+    fn trailing_bits_are_rejected() {
+        // "AB" has a set bit in the last symbol that isn't part of the single
+        // decoded byte.
+        assert_eq!(base64_decode("AB"), Err(DecodeError::InvalidLastSymbol(1, b'B')));
+    }
+
+    proptest! {
+        #[test]
+        fn base64_roundtrip(bytes in prop::collection::vec(any::<u8>(), 0..256)) {
+            let encoded = base64_encode(&bytes);
+            prop_assert_eq!(base64_decode(&encoded).expect("Encoded bytes should decode"), bytes);
+        }
+
+        #[test]
+        fn base64_decoding_never_panics(input in "[A-Za-z0-9+/=]{0,128}") {
+            if let Ok(decoded) = base64_decode(&input) {
+                prop_assert_eq!(base64_encode(decoded), input);
+            }
+        }
+    }
+
+    #[test]
+    fn base64_strings_are_compared_by_their_decoded_bytes() {
+        let key = base64_encode([1u8; 32]);
+        let other = base64_encode([2u8; 32]);
+
+        assert!(ct_eq_base64(&key, &key));
+        assert!(!ct_eq_base64(&key, &other));
+        assert!(!ct_eq_base64(&key, &key[..key.len() - 4]));
+        assert!(!ct_eq_base64("", &key));
+        assert!(ct_eq_base64("", ""));
+    }
+
+    #[test]
+    fn invalid_base64_never_compares_equal() {
+        let key = base64_encode([1u8; 32]);
+        let padded = base64::encode([1u8; 32]);
+
+        assert!(!ct_eq_base64(&padded, &key));
+        assert!(!ct_eq_base64(&padded, &padded));
+        assert!(!ct_eq_base64("AB", "AB"));
+        assert!(!ct_eq_base64("not base64!", "not base64!"));
+    }
+}